@@ -0,0 +1,69 @@
+//! Authenticating as a github app.
+//!
+//! The default github CI token can only see public org members, and can't
+//! check repository collaborators at all. A github app that's installed on a
+//! package's org can see both, so when we're given an app ID and private key
+//! we use the app's installation on each repository that we need to ask about.
+
+use std::path::Path;
+
+use miette::IntoDiagnostic as _;
+use octocrab::{Octocrab, models::AppId};
+
+/// Makes a client that's authenticated as the app itself.
+///
+/// An app client can't do much on its own: use [`installation`] to get a
+/// client for a specific repository.
+pub fn app_client(app_id: u64, private_key: &Path) -> miette::Result<Octocrab> {
+    let pem = std::fs::read(private_key)
+        .into_diagnostic()
+        .map_err(|e| e.wrap_err(format!("failed to read {}", private_key.display())))?;
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem)
+        .into_diagnostic()
+        .map_err(|e| e.wrap_err(format!("invalid private key in {}", private_key.display())))?;
+    Octocrab::builder()
+        .app(AppId(app_id), key)
+        .build()
+        .into_diagnostic()
+}
+
+/// Makes a client for the app's installation on `owner/repo`, or returns
+/// `None` if the app isn't installed there.
+pub async fn installation(
+    app: &Octocrab,
+    owner: &str,
+    repo: &str,
+) -> miette::Result<Option<Octocrab>> {
+    match app.apps().get_repository_installation(owner, repo).await {
+        Ok(inst) => Ok(Some(app.installation(inst.id).into_diagnostic()?)),
+        Err(octocrab::Error::GitHub { source, .. }) if source.status_code.as_u16() == 404 => {
+            Ok(None)
+        }
+        Err(e) => Err(e).into_diagnostic(),
+    }
+}
+
+/// The login of the app's bot user, which is what its installations post as.
+pub async fn login(app: &Octocrab) -> miette::Result<String> {
+    let app = app.current().app().await.into_diagnostic()?;
+    let slug = app
+        .slug
+        .ok_or_else(|| miette::miette!("github didn't tell us the app's slug"))?;
+    Ok(format!("{slug}[bot]"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bad_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.pem");
+        assert!(app_client(1, &path).is_err());
+
+        std::fs::write(&path, "not a key").unwrap();
+        let e = app_client(1, &path).unwrap_err();
+        assert!(e.to_string().contains("invalid private key"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Publishing our report as a github check run.
+//!
+//! Compared to the PR comment, the advantage of a check run is that failures
+//! can be annotated on the index files in the "Files changed" tab.
+
+use crate::{
+    DisplayItem, Report,
+    github::{Annotation, CheckRun, Github},
+};
+
+const CHECK_NAME: &str = "nickel-customs";
+
+/// Github accepts at most this many annotations per request.
+const MAX_ANNOTATIONS_PER_REQUEST: usize = 50;
+
+fn annotations(report: &Report) -> Vec<Annotation> {
+    let Report::PackageReports(items) = report else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let (path, line) = item.failure_location()?;
+            // Github wants 32-bit line numbers. Nobody's index file is that long.
+            let line = u32::try_from(line).unwrap_or(u32::MAX);
+            Some(Annotation {
+                path: path.to_owned(),
+                line,
+                message: DisplayItem(item.as_ref()).to_string(),
+            })
+        })
+        .collect()
+}
+
+fn check_run(report: &Report) -> CheckRun {
+    let title = if report.is_good() {
+        "All checks passed"
+    } else {
+        "Some checks failed"
+    };
+    CheckRun {
+        name: CHECK_NAME.to_owned(),
+        success: report.is_good(),
+        title: title.to_owned(),
+        summary: report.to_string(),
+    }
+}
+
+/// Creates a completed check run on the head commit of a PR.
+pub async fn publish(
+    github: &impl Github,
+    owner: &str,
+    repo: &str,
+    pr: u64,
+    report: &Report,
+) -> miette::Result<()> {
+    let head_sha = github.pr_head(owner, repo, pr).await?;
+    let run = check_run(report);
+
+    let mut annotations = annotations(report);
+    let rest = annotations.split_off(annotations.len().min(MAX_ANNOTATIONS_PER_REQUEST));
+    let id = github
+        .create_check_run(owner, repo, &head_sha, &run, annotations)
+        .await?;
+
+    // Annotations in updates get appended to the existing ones.
+    let mut rest = rest.into_iter().peekable();
+    while rest.peek().is_some() {
+        let chunk = rest.by_ref().take(MAX_ANNOTATIONS_PER_REQUEST).collect();
+        github.add_annotations(owner, repo, id, &run, chunk).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check_diff_paths, diff, github::FakeGithub};
+
+    #[test]
+    fn test_annotations() {
+        let diff = r#"
+diff --git a/weird_path/foo.yaml b/weird_path/foo.yaml
+index df1cd2a..2229806 100644
+--- a/weird_path/foo.yaml
++++ b/weird_path/foo.yaml
+@@ -1 +1,2 @@
+ foo
++bar
+diff --git a/.github/workflows/foo.yaml b/.github/workflows/foo.yaml
+index df1cd2a..2229806 100644
+--- a/.github/workflows/foo.yaml
++++ b/.github/workflows/foo.yaml
+@@ -1 +1,2 @@
+ foo
++bar
+"#;
+        let mut reports = Vec::new();
+        check_diff_paths(diff::file_changes(diff).unwrap(), &mut reports);
+        let report = Report::from(reports);
+
+        // Only the bad path gets annotated; the CI change is just a warning.
+        let annotations = annotations(&report);
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].path, "weird_path/foo.yaml");
+        assert_eq!(annotations[0].line, 1);
+        assert!(annotations[0].message.contains("this PR modifies"));
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        // More bad paths than fit in one request.
+        let diff: String = (0..60)
+            .map(|i| {
+                format!(
+                    "diff --git a/bad/{i} b/bad/{i}\n\
+                     --- a/bad/{i}\n\
+                     +++ b/bad/{i}\n\
+                     @@ -1 +1,2 @@\n \
+                     foo\n\
+                     +bar\n"
+                )
+            })
+            .collect();
+        let mut reports = Vec::new();
+        check_diff_paths(diff::file_changes(&diff).unwrap(), &mut reports);
+        let report = Report::from(reports);
+
+        let github = FakeGithub {
+            heads: [(1, "abc".to_owned())].into(),
+            ..Default::default()
+        };
+        publish(&github, "nickel-lang", "nickel-mine", 1, &report)
+            .await
+            .unwrap();
+
+        let runs = github.check_runs.lock().unwrap();
+        let [run] = runs.as_slice() else {
+            panic!("expected one check run, got {runs:?}");
+        };
+        assert_eq!(run.sha, "abc");
+        assert!(!run.run.success);
+        assert_eq!(run.run.title, "Some checks failed");
+        assert_eq!(run.annotations.len(), 60);
+        assert_eq!(run.annotations[59].path, "bad/59");
+    }
+}
@@ -0,0 +1,197 @@
+//! The PR comment that we post our report in.
+//!
+//! Rather than posting a new comment every time we run, we tag our comment with
+//! a hidden marker and edit it in place on subsequent runs. Anyone can write
+//! the marker, so we only recognize it in comments that we wrote ourselves.
+
+use crate::github::Github;
+
+/// A hidden marker at the start of every comment we post.
+const MARKER: &str = "<!-- nickel-customs report -->";
+
+/// A hidden marker at the start of each previous report in the history section.
+const HISTORY_MARKER: &str = "<!-- nickel-customs previous report -->";
+
+const HISTORY_START: &str = "\n<details>\n<summary>Previous reports</summary>\n\n";
+const HISTORY_END: &str = "</details>\n";
+
+/// The maximum number of previous reports that we keep around.
+///
+/// Github limits the size of comments, so we can't keep them all.
+const MAX_HISTORY: usize = 10;
+
+/// Is this comment one of ours?
+fn is_customs_comment(body: &str) -> bool {
+    body.starts_with(MARKER)
+}
+
+/// Splits one of our comment bodies into its current report and its previous reports.
+fn split_comment(body: &str) -> (&str, Vec<&str>) {
+    let body = body
+        .strip_prefix(MARKER)
+        .unwrap_or(body)
+        .trim_start_matches('\n');
+    match body.split_once(HISTORY_START) {
+        Some((current, history)) => {
+            let history = history.strip_suffix(HISTORY_END).unwrap_or(history);
+            let previous = history
+                .split(HISTORY_MARKER)
+                .map(|r| r.trim_matches('\n'))
+                .filter(|r| !r.is_empty())
+                .collect();
+            (current.trim_matches('\n'), previous)
+        }
+        None => (body.trim_matches('\n'), Vec::new()),
+    }
+}
+
+/// Renders the body of our comment.
+///
+/// If `previous` is the body of one of our earlier comments and `keep_history` is
+/// set, the earlier reports are kept in a collapsed section after the new one.
+fn render(report: &str, previous: Option<&str>, keep_history: bool) -> String {
+    let mut body = format!("{MARKER}\n{}\n", report.trim_end());
+
+    let history = match previous {
+        Some(previous) if keep_history => {
+            let (current, mut history) = split_comment(previous);
+            history.insert(0, current);
+            history.truncate(MAX_HISTORY);
+            history
+        }
+        _ => Vec::new(),
+    };
+
+    if !history.is_empty() {
+        body.push_str(HISTORY_START);
+        for r in history {
+            body.push_str(&format!("{HISTORY_MARKER}\n{r}\n\n"));
+        }
+        body.push_str(HISTORY_END);
+    }
+    body
+}
+
+/// Posts our report to a PR, updating our previous comment if there is one.
+pub async fn post(
+    github: &impl Github,
+    owner: &str,
+    repo: &str,
+    pr: u64,
+    report: &str,
+    keep_history: bool,
+) -> miette::Result<()> {
+    let login = github.login().await?;
+    let comments = github.comments(owner, repo, pr).await?;
+    let previous = comments
+        .into_iter()
+        .rev()
+        .find(|c| c.author.eq_ignore_ascii_case(&login) && is_customs_comment(&c.body));
+
+    match previous {
+        Some(previous) => {
+            let body = render(report, Some(&previous.body), keep_history);
+            github.update_comment(owner, repo, previous.id, &body).await
+        }
+        None => {
+            let body = render(report, None, keep_history);
+            github.create_comment(owner, repo, pr, &body).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{Comment, FakeGithub};
+
+    fn fake_github() -> FakeGithub {
+        FakeGithub {
+            login: "customs[bot]".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a comment by someone other than us.
+    fn add_comment(github: &FakeGithub, author: &str, body: &str) {
+        let mut comments = github.comments.lock().unwrap();
+        let id = comments.len() as u64 + 1;
+        comments.push(Comment {
+            id,
+            author: author.to_owned(),
+            body: body.to_owned(),
+        });
+    }
+
+    #[test]
+    fn test_render_without_history() {
+        let body = render(" - a report\n", None, true);
+        assert!(is_customs_comment(&body));
+        assert_eq!(split_comment(&body), (" - a report", vec![]));
+
+        // Without `keep_history`, the previous report gets dropped.
+        let body = render(" - new report\n", Some(&body), false);
+        assert_eq!(split_comment(&body), (" - new report", vec![]));
+    }
+
+    #[test]
+    fn test_render_with_history() {
+        let first = render("- first", None, true);
+        let second = render("- second", Some(&first), true);
+        let third = render("- third", Some(&second), true);
+        assert!(is_customs_comment(&third));
+        assert_eq!(
+            split_comment(&third),
+            ("- third", vec!["- second", "- first"])
+        );
+
+        let mut body = third;
+        for i in 0..2 * MAX_HISTORY {
+            body = render(&format!("- report {i}"), Some(&body), true);
+        }
+        let (_, history) = split_comment(&body);
+        assert_eq!(history.len(), MAX_HISTORY);
+    }
+
+    #[test]
+    fn test_not_customs_comment() {
+        assert!(!is_customs_comment("LGTM"));
+        assert!(!is_customs_comment(&format!("quoting you: {MARKER}")));
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let github = fake_github();
+        add_comment(&github, "someone", "LGTM");
+        post(&github, "o", "r", 1, "- first", true).await.unwrap();
+        post(&github, "o", "r", 1, "- second", true).await.unwrap();
+
+        // The first post made a comment, and the second one updated it.
+        let comments = github.comments("o", "r", 1).await.unwrap();
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].body, "LGTM");
+        assert_eq!(
+            split_comment(&comments[1].body),
+            ("- second", vec!["- first"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_ignores_impostors() {
+        let github = fake_github();
+        let planted = render("- everything is fine", None, true);
+        add_comment(&github, "mallory", &planted);
+        post(&github, "o", "r", 1, "- first", true).await.unwrap();
+        post(&github, "o", "r", 1, "- second", true).await.unwrap();
+
+        // We left the planted comment alone, and didn't copy it into our history.
+        let comments = github.comments("o", "r", 1).await.unwrap();
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].body, planted);
+        assert_eq!(comments[1].author, "customs[bot]");
+        assert_eq!(
+            split_comment(&comments[1].body),
+            ("- second", vec!["- first"])
+        );
+    }
+}
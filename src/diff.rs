@@ -0,0 +1,268 @@
+//! Splitting a diff into the changes that it makes to each file.
+//!
+//! [`gitpatch`] only looks at the `---` and `+++` lines of a diff, but git
+//! describes renames, deletions and mode changes in the extended header lines
+//! that come before them. A pure rename or mode change doesn't have `---` and
+//! `+++` lines at all, and gitpatch would skip right over it. So we split the
+//! diff into one section per file, classify each section from its headers,
+//! and only hand the sections with contents to gitpatch.
+
+use std::borrow::Cow;
+
+use gitpatch::{File, ParseError, Patch};
+
+/// What a diff does to a single file.
+#[derive(Clone, Debug)]
+pub enum FileChange<'a> {
+    /// A new file. The patch adds all of its lines.
+    Added(Patch<'a>),
+    Modified(Patch<'a>),
+    /// The file was moved, possibly with some changes to its contents.
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Deleted {
+        path: String,
+    },
+    /// The file's new contents aren't text, so there are no lines to look at.
+    Binary {
+        path: String,
+    },
+    /// The path is now something other than a regular file, like a symlink
+    /// or a submodule.
+    NotAFile {
+        path: String,
+        kind: String,
+    },
+    /// The file's mode changed, possibly with some changes to its contents.
+    ModeChanged {
+        path: String,
+        old_mode: String,
+        new_mode: String,
+    },
+}
+
+/// Splits a diff into per-file changes.
+///
+/// This understands `git diff` output and plain unified diffs; in the latter,
+/// only additions, deletions and renames can be recognized (from `/dev/null`
+/// or mismatched paths).
+pub fn file_changes(diff: &str) -> Result<Vec<FileChange<'_>>, ParseError<'_>> {
+    let mut sections = split_sections(diff);
+    let mut ret = Vec::new();
+    // Anything before the first `diff --git` line is either a preamble (like
+    // the commit message in `git format-patch` output) or a plain unified diff.
+    // If there's nothing else, it had better be a diff.
+    let preamble = sections.remove(0);
+    if sections.is_empty() || preamble.lines().any(|l| l.starts_with("--- ")) {
+        ret.extend(Patch::from_multiple(preamble)?.into_iter().map(classify));
+    }
+    for section in sections {
+        ret.push(git_section(section)?);
+    }
+    Ok(ret)
+}
+
+/// Splits a diff before each `diff --git` line. The first section is whatever
+/// comes before the first such line, and may be empty.
+fn split_sections(diff: &str) -> Vec<&str> {
+    let starts = diff
+        .match_indices("diff --git ")
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || diff.as_bytes()[i - 1] == b'\n');
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(starts)
+        .chain(std::iter::once(diff.len()))
+        .collect();
+    starts.windows(2).map(|w| &diff[w[0]..w[1]]).collect()
+}
+
+/// Classifies a patch from a plain unified diff, using its old and new paths.
+fn classify(patch: Patch<'_>) -> FileChange<'_> {
+    let old = strip_prefix(&patch.old.path, "a/");
+    let new = strip_prefix(&patch.new.path, "b/");
+    if patch.new.path == "/dev/null" {
+        FileChange::Deleted {
+            path: old.to_owned(),
+        }
+    } else if patch.old.path == "/dev/null" {
+        FileChange::Added(patch)
+    } else if old != new {
+        FileChange::Renamed {
+            from: old.to_owned(),
+            to: new.to_owned(),
+        }
+    } else {
+        FileChange::Modified(patch)
+    }
+}
+
+/// Classifies a section of a diff that starts with a `diff --git` line.
+fn git_section(section: &str) -> Result<FileChange<'_>, ParseError<'_>> {
+    let mut lines = section.lines();
+    // unwrap: sections are non-empty, because they start with "diff --git ".
+    let first = lines.next().unwrap();
+    let (old_path, new_path) = git_paths(first);
+
+    let mut rename_from = None;
+    let mut rename_to = None;
+    let mut old_mode = None;
+    let mut new_mode = None;
+    let mut deleted = false;
+    let mut binary = false;
+    let mut has_contents = false;
+    for line in lines {
+        if line.starts_with("--- ") {
+            has_contents = true;
+            break;
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            binary = true;
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            rename_from = Some(path);
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            rename_to = Some(path);
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            old_mode = Some(mode);
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            new_mode = Some(mode);
+        } else if line.starts_with("deleted file mode ") {
+            deleted = true;
+        }
+    }
+
+    if let (Some(from), Some(to)) = (rename_from, rename_to) {
+        return Ok(FileChange::Renamed {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
+    }
+    if deleted {
+        return Ok(FileChange::Deleted {
+            path: old_path.to_owned(),
+        });
+    }
+    if let (Some(old_mode), Some(new_mode)) = (old_mode, new_mode) {
+        return Ok(FileChange::ModeChanged {
+            path: new_path.to_owned(),
+            old_mode: old_mode.to_owned(),
+            new_mode: new_mode.to_owned(),
+        });
+    }
+    if binary {
+        return Ok(FileChange::Binary {
+            path: new_path.to_owned(),
+        });
+    }
+    if has_contents {
+        return Ok(classify(Patch::from_single(section)?));
+    }
+
+    // A new empty file: there are no lines to look at, but the rest of the
+    // checks still need to see the path.
+    let patch = Patch {
+        old: File {
+            path: Cow::Owned(format!("a/{old_path}")),
+            meta: None,
+        },
+        new: File {
+            path: Cow::Owned(format!("b/{new_path}")),
+            meta: None,
+        },
+        hunks: Vec::new(),
+        end_newline: true,
+    };
+    let is_new = section.lines().any(|l| l.starts_with("new file mode "));
+    Ok(if is_new {
+        FileChange::Added(patch)
+    } else {
+        FileChange::Modified(patch)
+    })
+}
+
+/// Gets the old and new paths (without their "a/" and "b/" prefixes) from a
+/// `diff --git a/<old> b/<new>` line.
+///
+/// This is ambiguous if the paths contain " b/", but index paths don't.
+fn git_paths(line: &str) -> (&str, &str) {
+    let paths = line.strip_prefix("diff --git ").unwrap_or(line);
+    match paths.split_once(" b/") {
+        Some((old, new)) => (strip_prefix(old, "a/"), new),
+        None => (paths, paths),
+    }
+}
+
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> &'a str {
+    path.strip_prefix(prefix).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_changes() {
+        let diff = r#"diff --git a/github/org/new b/github/org/new
+new file mode 100644
+index 0000000..1111111
+--- /dev/null
++++ b/github/org/new
+@@ -0,0 +1 @@
++line
+diff --git a/github/org/old b/github/org/old
+deleted file mode 100644
+index 1111111..0000000
+--- a/github/org/old
++++ /dev/null
+@@ -1 +0,0 @@
+-line
+diff --git a/github/org/a b/github/org/b
+similarity index 100%
+rename from github/org/a
+rename to github/org/b
+diff --git a/github/org/exe b/github/org/exe
+old mode 100644
+new mode 100755
+diff --git a/github/org/pkg b/github/org/pkg
+index 1111111..2222222 100644
+--- a/github/org/pkg
++++ b/github/org/pkg
+@@ -1 +1,2 @@
+ line
++line
+diff --git a/github/org/empty b/github/org/empty
+new file mode 100644
+index 0000000..e69de29
+diff --git a/github/org/bin b/github/org/bin
+index 1111111..3333333 100644
+Binary files a/github/org/bin and b/github/org/bin differ
+"#;
+        let changes = file_changes(diff).unwrap();
+        assert_eq!(changes.len(), 7);
+        assert!(matches!(&changes[0], FileChange::Added(p) if p.new.path == "b/github/org/new"));
+        assert!(matches!(&changes[1], FileChange::Deleted { path } if path == "github/org/old"));
+        assert!(matches!(
+            &changes[2],
+            FileChange::Renamed { from, to } if from == "github/org/a" && to == "github/org/b"
+        ));
+        assert!(matches!(
+            &changes[3],
+            FileChange::ModeChanged { path, old_mode, new_mode }
+                if path == "github/org/exe" && old_mode == "100644" && new_mode == "100755"
+        ));
+        assert!(matches!(&changes[4], FileChange::Modified(p) if p.hunks.len() == 1));
+        assert!(
+            matches!(&changes[5], FileChange::Added(p) if p.new.path == "b/github/org/empty" && p.hunks.is_empty())
+        );
+        assert!(matches!(&changes[6], FileChange::Binary { path } if path == "github/org/bin"));
+    }
+
+    #[test]
+    fn test_plain_diff() {
+        let diff = "--- a/github/org/pkg\n+++ /dev/null\n@@ -1 +0,0 @@\n-line\n";
+        let changes = file_changes(diff).unwrap();
+        assert!(matches!(&changes[..], [FileChange::Deleted { path }] if path == "github/org/pkg"));
+
+        assert!(file_changes("").is_err());
+    }
+}
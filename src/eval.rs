@@ -0,0 +1,688 @@
+//! Type-checking and evaluating a package's entry point.
+//!
+//! Evaluating a package means running arbitrary code, so we do it in a child
+//! process (by default, another invocation of ourselves with `--eval-package`;
+//! see [`ChildCommand`]). The parent
+//! resolves and downloads the package's dependencies and sends the child a
+//! package map on stdin. The child checks that the package only imports files
+//! from its own directory and from its dependencies, caps its own memory
+//! usage, and reports back by printing an [`EvalCheck`] as JSON on stdout. The
+//! parent kills it if it runs for too long.
+
+use std::{
+    collections::HashSet,
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use miette::IntoDiagnostic as _;
+use nickel_lang_core::{
+    bytecode::ast::{alloc::AstAlloc, compat::ToMainline as _},
+    cache::{InputFormat, SourceCache},
+    error::{
+        NullReporter,
+        report::{ColorOpt, report_as_str},
+    },
+    eval::cache::CacheImpl,
+    identifier::Ident,
+    package::PackageMap,
+    position::TermPos,
+    program::Program,
+    term::{Import, RichTerm, Term},
+    traverse::{Traverse as _, TraverseControl},
+    typecheck::TypecheckMode,
+};
+use nickel_lang_package::{ManifestFile, PreciseIndexPkg, PrecisePkg, resolve::Resolution};
+use serde::{Deserialize, Serialize};
+
+use crate::package::{self, IntoDiagnostic as _};
+
+/// The file that gets evaluated when someone imports a package.
+pub const ENTRY_POINT: &str = "main.ncl";
+
+/// How long we let a package evaluate by default, in seconds.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// How much memory we let a package use by default, in MiB.
+pub const DEFAULT_MEMORY_LIMIT_MIB: u64 = 4096;
+
+/// Resource limits for evaluating a package.
+#[derive(Clone, Debug)]
+pub struct EvalLimits {
+    pub timeout: Duration,
+    /// The maximum size of the evaluating process's address space, in MiB.
+    pub memory_limit_mib: u64,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            memory_limit_mib: DEFAULT_MEMORY_LIMIT_MIB,
+        }
+    }
+}
+
+/// The command that starts the child process.
+///
+/// The child gets `--eval-package <dir> --eval-memory-limit <mib>` appended to
+/// `args`, and must end up calling [`run_in_child`] with them.
+#[derive(Clone, Debug)]
+pub struct ChildCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl ChildCommand {
+    /// Another invocation of ourselves.
+    pub fn current_exe() -> std::io::Result<Self> {
+        Ok(Self {
+            program: std::env::current_exe()?,
+            args: Vec::new(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "outcome", content = "details", rename_all = "snake_case")]
+pub enum EvalCheck {
+    Ok,
+    NoEntryPoint,
+    /// We couldn't resolve or download the package's dependencies.
+    DependenciesFailed(String),
+    /// The entry point failed to parse or typecheck. Contains the rendered error.
+    TypecheckFailed(String),
+    /// The entry point failed to evaluate. Contains the rendered error.
+    EvalFailed(String),
+    /// The package imports a file from outside of its own directory and its
+    /// dependencies. Contains the import, as written.
+    ForbiddenImport(String),
+    /// Evaluation didn't finish in time. Contains the timeout, in seconds.
+    TimedOut(u64),
+    /// Evaluation tried to use more memory than it was allowed. Contains the
+    /// limit, in MiB.
+    OutOfMemory(u64),
+    /// The evaluating process died without telling us what happened.
+    Crashed(String),
+}
+
+impl EvalCheck {
+    /// Checks a package's entry point in a child process.
+    ///
+    /// `package_map` points at the package's dependencies, which must already
+    /// have been downloaded (see [`package_map`]).
+    pub fn new(package_dir: &Path, package_map: &PackageMap, limits: &EvalLimits) -> Self {
+        match ChildCommand::current_exe() {
+            Ok(child) => Self::with_child(package_dir, package_map, limits, &child),
+            Err(e) => EvalCheck::Crashed(e.to_string()),
+        }
+    }
+
+    /// Like [`EvalCheck::new`], but starts the child process with `child`.
+    pub fn with_child(
+        package_dir: &Path,
+        package_map: &PackageMap,
+        limits: &EvalLimits,
+        child: &ChildCommand,
+    ) -> Self {
+        if !package_dir.join(ENTRY_POINT).is_file() {
+            return EvalCheck::NoEntryPoint;
+        }
+
+        match run_child(package_dir, package_map, limits, child) {
+            Ok(check) => check,
+            Err(e) => EvalCheck::Crashed(e),
+        }
+    }
+
+    pub fn is_good(&self) -> bool {
+        matches!(self, EvalCheck::Ok)
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        match self {
+            EvalCheck::Ok => writeln!(f, "{indent}✅ typechecked and evaluated {ENTRY_POINT}"),
+            EvalCheck::NoEntryPoint => writeln!(f, "{indent}❌ package has no {ENTRY_POINT}"),
+            EvalCheck::DependenciesFailed(e) => writeln!(
+                f,
+                "{indent}❌ couldn't get dependencies to evaluate {ENTRY_POINT}: {e}"
+            ),
+            EvalCheck::TypecheckFailed(e) => {
+                writeln!(f, "{indent}❌ failed to typecheck {ENTRY_POINT}: {e}")
+            }
+            EvalCheck::EvalFailed(e) => {
+                writeln!(f, "{indent}❌ failed to evaluate {ENTRY_POINT}: {e}")
+            }
+            EvalCheck::ForbiddenImport(import) => writeln!(
+                f,
+                "{indent}❌ package imports `{import}`, which is outside of the package and its dependencies"
+            ),
+            EvalCheck::TimedOut(secs) => writeln!(
+                f,
+                "{indent}❌ evaluating {ENTRY_POINT} took more than {secs} seconds"
+            ),
+            EvalCheck::OutOfMemory(mib) => writeln!(
+                f,
+                "{indent}❌ evaluating {ENTRY_POINT} used more than {mib} MiB of memory"
+            ),
+            EvalCheck::Crashed(e) => {
+                writeln!(f, "{indent}❌ evaluating {ENTRY_POINT} crashed: {e}")
+            }
+        }
+    }
+}
+
+/// A [`PackageMap`], in a form that we can send to the child process.
+#[derive(Serialize, Deserialize)]
+struct SerializedPackageMap {
+    top_level: Vec<(String, PathBuf)>,
+    packages: Vec<(PathBuf, String, PathBuf)>,
+}
+
+impl From<&PackageMap> for SerializedPackageMap {
+    fn from(map: &PackageMap) -> Self {
+        Self {
+            top_level: map
+                .top_level
+                .iter()
+                .map(|(name, path)| (name.label().to_owned(), path.clone()))
+                .collect(),
+            packages: map
+                .packages
+                .iter()
+                .map(|((parent, name), path)| {
+                    (parent.clone(), name.label().to_owned(), path.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SerializedPackageMap> for PackageMap {
+    fn from(map: SerializedPackageMap) -> Self {
+        Self {
+            top_level: map
+                .top_level
+                .into_iter()
+                .map(|(name, path)| (Ident::new(name), path))
+                .collect(),
+            packages: map
+                .packages
+                .into_iter()
+                .map(|(parent, name, path)| ((parent, Ident::new(name)), path))
+                .collect(),
+        }
+    }
+}
+
+fn run_child(
+    package_dir: &Path,
+    package_map: &PackageMap,
+    limits: &EvalLimits,
+    command: &ChildCommand,
+) -> Result<EvalCheck, String> {
+    let mut child = Command::new(&command.program)
+        .args(&command.args)
+        .arg("--eval-package")
+        .arg(package_dir)
+        .arg("--eval-memory-limit")
+        .arg(limits.memory_limit_mib.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // The child reads all of stdin before doing anything else, so this can't
+    // block for long. If it fails, the child will tell us about it.
+    // unwrap: we asked for stdin above, and the map only has string keys.
+    let map = serde_json::to_vec(&SerializedPackageMap::from(package_map)).unwrap();
+    let _ = child.stdin.take().unwrap().write_all(&map);
+
+    // Drain the pipes on other threads, so that a chatty child can't block on a
+    // full pipe while we're waiting for it to exit.
+    let drain = |mut pipe: Box<dyn std::io::Read + Send>| {
+        std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = pipe.read_to_string(&mut buf);
+            buf
+        })
+    };
+    // unwrap: we asked for both pipes above.
+    let stdout = drain(Box::new(child.stdout.take().unwrap()));
+    let stderr = drain(Box::new(child.stderr.take().unwrap()));
+
+    let deadline = Instant::now() + limits.timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(EvalCheck::TimedOut(limits.timeout.as_secs()));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    // unwrap: the draining threads don't panic.
+    let stdout = stdout.join().unwrap();
+    let stderr = stderr.join().unwrap();
+    // The child's report is the last thing that it prints; anything before it
+    // isn't ours to interpret.
+    if let Some(check) = stdout
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str(line).ok())
+    {
+        return Ok(check);
+    }
+    // Rust aborts with this message when an allocation fails, which is what
+    // hitting the address space limit looks like.
+    if stderr.contains("memory allocation of") {
+        return Ok(EvalCheck::OutOfMemory(limits.memory_limit_mib));
+    }
+    let stderr = stderr.trim();
+    Err(if stderr.is_empty() {
+        status.to_string()
+    } else {
+        format!("{status}: {stderr}")
+    })
+}
+
+/// The entry point of the child process: reads the package map from stdin,
+/// evaluates the package and prints the result.
+pub fn run_in_child(package_dir: &Path, memory_limit_mib: u64) {
+    limit_memory(memory_limit_mib);
+    let mut map = String::new();
+    let check = match std::io::stdin().read_to_string(&mut map) {
+        Ok(_) => match serde_json::from_str::<SerializedPackageMap>(&map) {
+            Ok(map) => evaluate(package_dir, &map.into()),
+            Err(e) => EvalCheck::Crashed(format!("bad package map: {e}")),
+        },
+        Err(e) => EvalCheck::Crashed(format!("couldn't read package map: {e}")),
+    };
+    // unwrap: all of our maps have string keys, so serialization can't fail.
+    println!("{}", serde_json::to_string(&check).unwrap());
+}
+
+#[cfg(unix)]
+fn limit_memory(mib: u64) {
+    let bytes = mib.saturating_mul(1024 * 1024);
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+    // SAFETY: `limit` is a valid rlimit, and setrlimit doesn't keep the pointer around.
+    // If this fails we just run without a limit; the timeout still applies.
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_AS, &limit);
+    }
+}
+
+#[cfg(not(unix))]
+fn limit_memory(_mib: u64) {}
+
+/// Downloads the index packages that a package's dependencies resolved to,
+/// and makes a package map that points at them.
+///
+/// `resolution` is `None` if the package has no dependencies. The packages
+/// are fetched from wherever the resolution's config says that github
+/// packages come from, just like the package itself. (The package manager's
+/// `ensure_index_packages_downloaded` always fetches them from github.)
+pub fn package_map(
+    resolution: Option<&Resolution>,
+    manifest: &ManifestFile,
+) -> miette::Result<PackageMap> {
+    let Some(resolution) = resolution else {
+        return Ok(PackageMap::default());
+    };
+    for precise in resolution.all_packages() {
+        let PrecisePkg::Index(PreciseIndexPkg { id, version }) = &precise else {
+            continue;
+        };
+        // This is where the package manager would download it to, so that's
+        // where the package map will point.
+        let target = precise.local_path(&resolution.config);
+        if target.exists() {
+            continue;
+        }
+        let pkg = resolution.index.package(id, version).into_diag()?;
+        // unwrap: the local path of an index package always has a parent.
+        let parent = target.parent().unwrap();
+        std::fs::create_dir_all(parent).into_diagnostic()?;
+        let tmp = tempfile::tempdir_in(parent).into_diagnostic()?;
+        package::fetch(&pkg, tmp.path(), &resolution.config)?;
+        // Another check may have downloaded the same package in the meantime.
+        match std::fs::rename(tmp.path(), &target) {
+            Err(e) if !target.exists() => return Err(e).into_diagnostic(),
+            _ => {}
+        }
+    }
+    resolution.package_map(manifest).into_diag()
+}
+
+/// Ways in which a package's imports can fail our checks.
+#[derive(Debug)]
+pub enum ImportError {
+    /// One of the package's files failed to parse. Contains the rendered error.
+    Parse(String),
+    /// The package imports a file that it shouldn't. Contains the import, as written.
+    Forbidden(String),
+}
+
+/// Checks that evaluating `entry` can only read files in `package_dir` and in
+/// the packages in `package_map`.
+///
+/// Nickel will import any file that we can read, and the contents of imported
+/// files can end up in error messages, which we post publicly. Import paths
+/// are string literals, so we can find them all by parsing the files that
+/// would be imported (which we only do once we know they're allowed), without
+/// evaluating anything.
+pub fn check_imports(
+    entry: &Path,
+    package_dir: &Path,
+    package_map: &PackageMap,
+) -> Result<(), ImportError> {
+    let allowed: Vec<PathBuf> = std::iter::once(package_dir)
+        .chain(package_map.top_level.values().map(PathBuf::as_path))
+        .chain(package_map.packages.values().map(PathBuf::as_path))
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect();
+
+    // The files to look at: their paths, their formats, the packages that
+    // they were imported as (if they were imported as packages), and the
+    // imports that led to them, as written.
+    let mut todo = vec![(
+        entry.to_owned(),
+        InputFormat::Nickel,
+        None::<PathBuf>,
+        entry.display().to_string(),
+    )];
+    let mut seen = HashSet::new();
+    // A file that doesn't parse doesn't stop us from checking the others:
+    // forbidden imports are more important to report.
+    let mut parse_error = None;
+    while let Some((path, format, package, written)) = todo.pop() {
+        // A file that doesn't exist can't leak anything, and nickel will
+        // complain about it when evaluating.
+        let Ok(canonical) = path.canonicalize() else {
+            continue;
+        };
+        if !allowed.iter().any(|dir| canonical.starts_with(dir)) {
+            return Err(ImportError::Forbidden(written));
+        }
+        if format != InputFormat::Nickel || !seen.insert((canonical, package.clone())) {
+            continue;
+        }
+
+        let term = match parse(&path) {
+            Ok(term) => term,
+            Err(e) => {
+                parse_error.get_or_insert(e);
+                continue;
+            }
+        };
+        for import in imports(&term) {
+            match import {
+                Import::Path {
+                    path: import,
+                    format,
+                } => {
+                    // unwrap: we only look at files, which have parents.
+                    let dir = path.parent().unwrap();
+                    let written = import.to_string_lossy().into_owned();
+                    todo.push((dir.join(import), format, None, written));
+                }
+                Import::Package { id } => {
+                    // Nickel will complain about unknown packages when evaluating.
+                    if let Ok(dir) = package_map.get(package.as_deref(), id, TermPos::None) {
+                        let main = dir.join(ENTRY_POINT);
+                        let written = id.label().to_owned();
+                        todo.push((main, InputFormat::Nickel, Some(dir.to_owned()), written));
+                    }
+                }
+            }
+        }
+    }
+    parse_error.map_or(Ok(()), |e| Err(ImportError::Parse(e)))
+}
+
+/// Parses a nickel file, without resolving its imports.
+fn parse(path: &Path) -> Result<RichTerm, String> {
+    let mut sources = SourceCache::new();
+    let id = sources
+        .add_file(path, InputFormat::Nickel)
+        .map_err(|e| e.to_string())?;
+    let alloc = AstAlloc::new();
+    match sources.parse_nickel(&alloc, id) {
+        Ok(ast) => Ok(ast.to_mainline()),
+        Err(e) => Err(report_as_str(&mut sources.files, e, ColorOpt::Never)),
+    }
+}
+
+/// All the imports in a term.
+fn imports(term: &RichTerm) -> Vec<Import> {
+    let mut ret = Vec::new();
+    term.traverse_ref(
+        &mut |t: &RichTerm, _: &()| {
+            if let Term::Import(import) = t.as_ref() {
+                ret.push(import.clone());
+            }
+            TraverseControl::<(), ()>::Continue
+        },
+        &(),
+    );
+    ret
+}
+
+/// Typechecks and deep-evaluates a package's entry point, in this process.
+fn evaluate(package_dir: &Path, package_map: &PackageMap) -> EvalCheck {
+    let path = package_dir.join(ENTRY_POINT);
+    match check_imports(&path, package_dir, package_map) {
+        Ok(()) => {}
+        Err(ImportError::Parse(e)) => return EvalCheck::TypecheckFailed(e),
+        Err(ImportError::Forbidden(import)) => return EvalCheck::ForbiddenImport(import),
+    }
+
+    let mut prog: Program<CacheImpl> =
+        match Program::new_from_file(&path, std::io::sink(), NullReporter {}) {
+            Ok(prog) => prog,
+            Err(e) => return EvalCheck::TypecheckFailed(e.to_string()),
+        };
+    prog.set_package_map(package_map.clone());
+
+    // ANSI codes don't get rendered in github comments, so turn off color.
+    if let Err(e) = prog.typecheck(TypecheckMode::Walk) {
+        return EvalCheck::TypecheckFailed(report_as_str(&mut prog.files(), e, ColorOpt::Never));
+    }
+    if let Err(e) = prog.eval_deep() {
+        return EvalCheck::EvalFailed(report_as_str(&mut prog.files(), e, ColorOpt::Never));
+    }
+    EvalCheck::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use nickel_lang_package::manifest::MANIFEST_NAME;
+
+    use super::*;
+
+    fn package(main: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(MANIFEST_NAME),
+            r#"{ name = "foo", version = "1.0.0", minimal_nickel_version = "1.11.0", authors = [], description = "hi" }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(ENTRY_POINT), main).unwrap();
+        dir
+    }
+
+    fn check(main: &str) -> EvalCheck {
+        evaluate(package(main).path(), &PackageMap::default())
+    }
+
+    /// Checks a package in a child process that runs [`eval_child`] in this
+    /// test binary.
+    fn check_in_child(main: &str, limits: &EvalLimits) -> EvalCheck {
+        let child = ChildCommand {
+            program: std::env::current_exe().unwrap(),
+            args: [
+                "eval::tests::eval_child",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+                "--quiet",
+                "--test-threads=1",
+                // Our arguments go after this, where the test harness takes
+                // them as filters that don't match anything else.
+                "--",
+            ]
+            .map(String::from)
+            .to_vec(),
+        };
+        let dir = package(main);
+        EvalCheck::with_child(dir.path(), &PackageMap::default(), limits, &child)
+    }
+
+    #[test]
+    #[ignore = "the child process of check_in_child"]
+    fn eval_child() {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = |name: &str| {
+            let i = args.iter().position(|a| a == name)?;
+            args.get(i + 1)
+        };
+        let (Some(dir), Some(mib)) = (arg("--eval-package"), arg("--eval-memory-limit")) else {
+            return;
+        };
+        run_in_child(Path::new(dir), mib.parse().unwrap());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert!(matches!(check("{ x : Number = 1 }"), EvalCheck::Ok));
+        assert!(matches!(
+            check("{ x : Number = \"one\" }"),
+            EvalCheck::TypecheckFailed(_)
+        ));
+        assert!(matches!(check("{ x = "), EvalCheck::TypecheckFailed(_)));
+
+        // Deep evaluation finds errors that are nested in records.
+        let EvalCheck::EvalFailed(e) = check("{ x = { y = std.fail_with \"boom\" } }") else {
+            panic!("expected an evaluation failure");
+        };
+        assert!(e.contains("boom"));
+        assert!(!e.contains('\u{1b}'));
+    }
+
+    #[test]
+    fn test_check_imports() {
+        let root = tempfile::tempdir().unwrap();
+        let pkg = root.path().join("pkg");
+        let dep = root.path().join("dep");
+        std::fs::create_dir_all(pkg.join("lib")).unwrap();
+        std::fs::create_dir(&dep).unwrap();
+        std::fs::write(root.path().join("secret.txt"), "hunter2").unwrap();
+        std::fs::write(dep.join(ENTRY_POINT), "1").unwrap();
+        std::fs::write(pkg.join("lib/ok.ncl"), "import \"../data.json\"").unwrap();
+        std::fs::write(pkg.join("data.json"), "{}").unwrap();
+        std::fs::write(
+            pkg.join("lib/bad.ncl"),
+            "import \"../../secret.txt\" as 'Text",
+        )
+        .unwrap();
+
+        let map = PackageMap {
+            top_level: [(Ident::new("dep"), dep.clone())].into_iter().collect(),
+            packages: Default::default(),
+        };
+        let check = |main: &str| {
+            std::fs::write(pkg.join(ENTRY_POINT), main).unwrap();
+            check_imports(&pkg.join(ENTRY_POINT), &pkg, &map)
+        };
+
+        assert!(check("[import \"lib/ok.ncl\", import dep, import \"missing.ncl\"]").is_ok());
+        assert!(matches!(
+            check("{ x | String = import \"lib/bad.ncl\" }"),
+            Err(ImportError::Forbidden(i)) if i == "../../secret.txt"
+        ));
+        assert!(matches!(
+            check(&format!(
+                "import \"{}\"",
+                root.path().join("secret.txt").display()
+            )),
+            Err(ImportError::Forbidden(_))
+        ));
+        assert!(matches!(check("{ x = "), Err(ImportError::Parse(_))));
+
+        // A file that doesn't parse doesn't hide other forbidden imports.
+        std::fs::write(pkg.join("lib/broken.ncl"), "{ x = ").unwrap();
+        assert!(matches!(
+            check("[import \"lib/broken.ncl\", import \"../secret.txt\" as 'Text]"),
+            Err(ImportError::Forbidden(_))
+        ));
+
+        #[cfg(unix)]
+        {
+            // Symlinks don't get around the check.
+            std::os::unix::fs::symlink(root.path().join("secret.txt"), pkg.join("link.txt"))
+                .unwrap();
+            assert!(matches!(
+                check("import \"link.txt\" as 'Text"),
+                Err(ImportError::Forbidden(i)) if i == "link.txt"
+            ));
+        }
+
+        // Forbidden imports are caught before anything gets evaluated.
+        std::fs::write(pkg.join(ENTRY_POINT), "import \"../secret.txt\" as 'Text").unwrap();
+        assert!(matches!(
+            evaluate(&pkg, &map),
+            EvalCheck::ForbiddenImport(i) if i == "../secret.txt"
+        ));
+    }
+
+    #[test]
+    fn test_no_entry_point() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            EvalCheck::new(dir.path(), &PackageMap::default(), &EvalLimits::default()),
+            EvalCheck::NoEntryPoint
+        ));
+    }
+
+    #[test]
+    fn test_child_limits() {
+        assert!(matches!(
+            check_in_child("{ x : Number = 1 }", &EvalLimits::default()),
+            EvalCheck::Ok
+        ));
+
+        let limits = EvalLimits {
+            timeout: Duration::from_secs(1),
+            ..EvalLimits::default()
+        };
+        assert!(matches!(
+            check_in_child("let rec loop = fun x => loop x in loop 0", &limits),
+            EvalCheck::TimedOut(1)
+        ));
+
+        let limits = EvalLimits {
+            memory_limit_mib: 512,
+            ..EvalLimits::default()
+        };
+        assert!(matches!(
+            check_in_child("std.array.generate (fun i => i) 100000000", &limits),
+            EvalCheck::OutOfMemory(512)
+        ));
+
+        // Neither of those took us down with them.
+        assert!(matches!(
+            check_in_child("{ x : Number = 1 }", &EvalLimits::default()),
+            EvalCheck::Ok
+        ));
+    }
+}
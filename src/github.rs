@@ -0,0 +1,579 @@
+//! Talking to github.
+//!
+//! Everything we ask github goes through the [`Github`] trait, so that tests
+//! can run the whole pipeline against an in-memory fake instead of the API.
+
+use std::path::Path;
+
+use miette::IntoDiagnostic as _;
+use octocrab::{
+    Octocrab,
+    commits::PullRequestTarget,
+    params::checks::{
+        CheckRunConclusion, CheckRunOutput, CheckRunOutputAnnotation,
+        CheckRunOutputAnnotationLevel, CheckRunStatus,
+    },
+};
+use serde::Deserialize;
+
+use crate::app;
+
+/// A comment on a PR.
+#[derive(Clone, Debug)]
+pub struct Comment {
+    pub id: u64,
+    /// The login of whoever wrote the comment.
+    pub author: String,
+    pub body: String,
+}
+
+/// What a completed check run says about a commit.
+#[derive(Clone, Debug)]
+pub struct CheckRun {
+    pub name: String,
+    pub success: bool,
+    pub title: String,
+    /// The body of the check run, in markdown.
+    pub summary: String,
+}
+
+/// A failure on a line of a file, shown next to the file in a check run.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub path: String,
+    pub line: u32,
+    pub message: String,
+}
+
+pub trait Github: Sized {
+    /// Returns a client for our github app's installation on `owner/repo`, or
+    /// `None` if we aren't a github app or it isn't installed there.
+    ///
+    /// Installations can see private org members and repository collaborators.
+    async fn installation(&self, owner: &str, repo: &str) -> miette::Result<Option<Self>>;
+
+    /// Fetches the unified diff of a PR.
+    async fn pr_diff(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<String>;
+
+    /// Is `user` a member of `org`? Without an installation on the org, we
+    /// only see public members.
+    async fn is_org_member(&self, org: &str, user: &str) -> miette::Result<bool>;
+
+    /// Is `user` a collaborator on `owner/repo`?
+    async fn is_collaborator(&self, owner: &str, repo: &str, user: &str) -> miette::Result<bool>;
+
+    /// Is `user` an active member of the team `slug` in `org`?
+    async fn is_team_member(&self, org: &str, slug: &str, user: &str) -> miette::Result<bool>;
+
+    /// The github logins of the people who opened the merged PRs that
+    /// contain a commit.
+    async fn pr_authors(&self, owner: &str, repo: &str, sha: &str) -> miette::Result<Vec<String>>;
+
+    /// The login that the comments we post show up under.
+    async fn login(&self) -> miette::Result<String>;
+
+    /// All the comments on a PR, oldest first.
+    async fn comments(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<Vec<Comment>>;
+
+    async fn create_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr: u64,
+        body: &str,
+    ) -> miette::Result<()>;
+
+    async fn update_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        id: u64,
+        body: &str,
+    ) -> miette::Result<()>;
+
+    /// The sha of a PR's head commit.
+    async fn pr_head(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<String>;
+
+    /// Creates a completed check run on the commit `sha`, and returns its id.
+    async fn create_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        run: &CheckRun,
+        annotations: Vec<Annotation>,
+    ) -> miette::Result<u64>;
+
+    /// Adds annotations to a check run, after the ones that it already has.
+    async fn add_annotations(
+        &self,
+        owner: &str,
+        repo: &str,
+        id: u64,
+        run: &CheckRun,
+        annotations: Vec<Annotation>,
+    ) -> miette::Result<()>;
+}
+
+/// Is this a "not found" error? Github uses those to say that someone isn't a
+/// member of something, and also when we aren't allowed to know.
+fn is_not_found(e: &octocrab::Error) -> bool {
+    matches!(e, octocrab::Error::GitHub { source, .. } if source.status_code.as_u16() == 404)
+}
+
+/// The login that github shows for comments made with the default CI token.
+const ACTIONS_LOGIN: &str = "github-actions[bot]";
+
+/// The real github API.
+pub struct GithubClient {
+    client: Octocrab,
+    /// If we're authenticated as a github app, a client for the app itself.
+    app: Option<Octocrab>,
+    /// If we're authenticated as a github app, the login of its bot user.
+    app_login: Option<String>,
+}
+
+impl GithubClient {
+    /// Makes a client that authenticates with a personal token, or not at all.
+    pub fn with_token(token: Option<String>) -> miette::Result<Self> {
+        let mut builder = Octocrab::builder();
+        if let Some(tok) = token {
+            builder = builder.personal_token(tok);
+        }
+        Ok(Self {
+            client: builder.build().into_diagnostic()?,
+            app: None,
+            app_login: None,
+        })
+    }
+
+    /// Makes a client that authenticates as a github app, using its
+    /// installation on `owner/repo`.
+    pub async fn with_app(
+        app_id: u64,
+        private_key: &Path,
+        owner: &str,
+        repo: &str,
+    ) -> miette::Result<Self> {
+        let app = app::app_client(app_id, private_key)?;
+        let client = app::installation(&app, owner, repo)
+            .await?
+            .ok_or_else(|| miette::miette!("the github app isn't installed on {owner}/{repo}"))?;
+        let app_login = app::login(&app).await?;
+        Ok(Self {
+            client,
+            app: Some(app),
+            app_login: Some(app_login),
+        })
+    }
+}
+
+/// Converts a check run into what octocrab sends to github.
+fn check_run_output(run: &CheckRun, annotations: Vec<Annotation>) -> CheckRunOutput {
+    CheckRunOutput {
+        title: run.title.clone(),
+        summary: run.summary.clone(),
+        text: None,
+        annotations: annotations
+            .into_iter()
+            .map(|a| CheckRunOutputAnnotation {
+                path: a.path,
+                start_line: a.line,
+                end_line: a.line,
+                start_column: None,
+                end_column: None,
+                annotation_level: CheckRunOutputAnnotationLevel::Failure,
+                message: a.message,
+                title: None,
+                raw_details: None,
+            })
+            .collect(),
+        images: Vec::new(),
+    }
+}
+
+impl Github for GithubClient {
+    async fn installation(&self, owner: &str, repo: &str) -> miette::Result<Option<Self>> {
+        let Some(app) = &self.app else {
+            return Ok(None);
+        };
+        Ok(app::installation(app, owner, repo)
+            .await?
+            .map(|client| Self {
+                client,
+                app: None,
+                app_login: self.app_login.clone(),
+            }))
+    }
+
+    async fn pr_diff(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<String> {
+        self.client
+            .pulls(owner, repo)
+            .get_diff(pr)
+            .await
+            .into_diagnostic()
+    }
+
+    async fn is_org_member(&self, org: &str, user: &str) -> miette::Result<bool> {
+        self.client
+            .orgs(org)
+            .check_membership(user)
+            .await
+            .into_diagnostic()
+    }
+
+    async fn is_collaborator(&self, owner: &str, repo: &str, user: &str) -> miette::Result<bool> {
+        self.client
+            .repos(owner, repo)
+            .is_collaborator(user)
+            .await
+            .into_diagnostic()
+    }
+
+    // This needs a token that can read the team's org; github says that the
+    // user isn't a member if we can't see the team.
+    async fn is_team_member(&self, org: &str, slug: &str, user: &str) -> miette::Result<bool> {
+        #[derive(Deserialize)]
+        struct Membership {
+            state: String,
+        }
+
+        let route = format!("/orgs/{org}/teams/{slug}/memberships/{user}");
+        match self.client.get::<Membership, _, ()>(route, None).await {
+            Ok(m) => Ok(m.state == "active"),
+            Err(e) if is_not_found(&e) => Ok(false),
+            Err(e) => Err(e).into_diagnostic(),
+        }
+    }
+
+    async fn pr_authors(&self, owner: &str, repo: &str, sha: &str) -> miette::Result<Vec<String>> {
+        let pulls = self
+            .client
+            .commits(owner, repo)
+            .associated_pull_requests(PullRequestTarget::Sha(sha.to_owned()))
+            .per_page(100)
+            .send()
+            .await
+            .into_diagnostic()?;
+        Ok(pulls
+            .items
+            .into_iter()
+            .filter(|pr| pr.merged_at.is_some())
+            .filter_map(|pr| pr.user.map(|u| u.login))
+            .collect())
+    }
+
+    async fn login(&self) -> miette::Result<String> {
+        if let Some(login) = &self.app_login {
+            return Ok(login.clone());
+        }
+        match self.client.current().user().await {
+            Ok(user) => Ok(user.login),
+            // Tokens that github makes for CI jobs don't belong to a user, and
+            // aren't allowed to ask who they are.
+            Err(octocrab::Error::GitHub { source, .. }) if source.status_code.as_u16() == 403 => {
+                Ok(ACTIONS_LOGIN.to_owned())
+            }
+            Err(e) => Err(e).into_diagnostic(),
+        }
+    }
+
+    async fn comments(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<Vec<Comment>> {
+        let first_page = self
+            .client
+            .issues(owner, repo)
+            .list_comments(pr)
+            .per_page(100)
+            .send()
+            .await
+            .into_diagnostic()?;
+        let comments = self.client.all_pages(first_page).await.into_diagnostic()?;
+        Ok(comments
+            .into_iter()
+            .map(|c| Comment {
+                id: c.id.into_inner(),
+                author: c.user.login,
+                body: c.body.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn create_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr: u64,
+        body: &str,
+    ) -> miette::Result<()> {
+        self.client
+            .issues(owner, repo)
+            .create_comment(pr, body)
+            .await
+            .into_diagnostic()?;
+        Ok(())
+    }
+
+    // octocrab's `update_comment` sends a POST, but github documents this endpoint as a PATCH.
+    async fn update_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        id: u64,
+        body: &str,
+    ) -> miette::Result<()> {
+        let route = format!("/repos/{owner}/{repo}/issues/comments/{id}");
+        let _: octocrab::models::issues::Comment = self
+            .client
+            .patch(route, Some(&serde_json::json!({ "body": body })))
+            .await
+            .into_diagnostic()?;
+        Ok(())
+    }
+
+    async fn pr_head(&self, owner: &str, repo: &str, pr: u64) -> miette::Result<String> {
+        let pr = self
+            .client
+            .pulls(owner, repo)
+            .get(pr)
+            .await
+            .into_diagnostic()?;
+        Ok(pr.head.sha)
+    }
+
+    async fn create_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        run: &CheckRun,
+        annotations: Vec<Annotation>,
+    ) -> miette::Result<u64> {
+        let conclusion = if run.success {
+            CheckRunConclusion::Success
+        } else {
+            CheckRunConclusion::Failure
+        };
+        let created = self
+            .client
+            .checks(owner, repo)
+            .create_check_run(&run.name, sha)
+            .status(CheckRunStatus::Completed)
+            .conclusion(conclusion)
+            .output(check_run_output(run, annotations))
+            .send()
+            .await
+            .into_diagnostic()?;
+        Ok(created.id.into_inner())
+    }
+
+    async fn add_annotations(
+        &self,
+        owner: &str,
+        repo: &str,
+        id: u64,
+        run: &CheckRun,
+        annotations: Vec<Annotation>,
+    ) -> miette::Result<()> {
+        self.client
+            .checks(owner, repo)
+            .update_check_run(id.into())
+            .output(check_run_output(run, annotations))
+            .send()
+            .await
+            .into_diagnostic()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeGithub;
+
+#[cfg(test)]
+mod fake {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// An in-memory github, for tests.
+    ///
+    /// Repositories and teams are written as `<owner>/<name>`.
+    #[derive(Clone, Default)]
+    pub struct FakeGithub {
+        pub diffs: HashMap<u64, String>,
+        /// Pairs of (org, user).
+        pub public_members: HashSet<(String, String)>,
+        /// Pairs of (org, user). Only an installation can see these.
+        pub private_members: HashSet<(String, String)>,
+        /// Pairs of (repository, user). Only an installation can see these.
+        pub collaborators: HashSet<(String, String)>,
+        /// Pairs of (team, user).
+        pub team_members: HashSet<(String, String)>,
+        /// Commit hashes and the logins of the people who opened the merged
+        /// PRs that contain them.
+        pub pr_authors: HashMap<String, Vec<String>>,
+        /// The repositories that our github app is installed on.
+        pub installed: HashSet<String>,
+        /// Is this client an app installation?
+        pub is_installation: bool,
+        /// The login that we post comments as.
+        pub login: String,
+        /// The comments on the PR, shared between this client and its installations.
+        pub comments: Arc<Mutex<Vec<Comment>>>,
+        /// The head commits of PRs.
+        pub heads: HashMap<u64, String>,
+        /// The check runs that we created, shared between this client and its installations.
+        pub check_runs: Arc<Mutex<Vec<FakeCheckRun>>>,
+    }
+
+    /// A check run on a commit, along with all of its annotations.
+    #[derive(Debug)]
+    pub struct FakeCheckRun {
+        pub sha: String,
+        pub run: CheckRun,
+        pub annotations: Vec<Annotation>,
+    }
+
+    fn pair(a: &str, b: &str) -> (String, String) {
+        (a.to_owned(), b.to_owned())
+    }
+
+    impl Github for FakeGithub {
+        async fn installation(&self, owner: &str, repo: &str) -> miette::Result<Option<Self>> {
+            Ok(self
+                .installed
+                .contains(&format!("{owner}/{repo}"))
+                .then(|| Self {
+                    is_installation: true,
+                    ..self.clone()
+                }))
+        }
+
+        async fn pr_diff(&self, _owner: &str, _repo: &str, pr: u64) -> miette::Result<String> {
+            self.diffs
+                .get(&pr)
+                .cloned()
+                .ok_or_else(|| miette::miette!("no PR #{pr}"))
+        }
+
+        async fn is_org_member(&self, org: &str, user: &str) -> miette::Result<bool> {
+            Ok(self.public_members.contains(&pair(org, user))
+                || (self.is_installation && self.private_members.contains(&pair(org, user))))
+        }
+
+        async fn is_collaborator(
+            &self,
+            owner: &str,
+            repo: &str,
+            user: &str,
+        ) -> miette::Result<bool> {
+            Ok(self.is_installation
+                && self
+                    .collaborators
+                    .contains(&pair(&format!("{owner}/{repo}"), user)))
+        }
+
+        async fn is_team_member(&self, org: &str, slug: &str, user: &str) -> miette::Result<bool> {
+            Ok(self
+                .team_members
+                .contains(&pair(&format!("{org}/{slug}"), user)))
+        }
+
+        async fn pr_authors(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            sha: &str,
+        ) -> miette::Result<Vec<String>> {
+            Ok(self.pr_authors.get(sha).cloned().unwrap_or_default())
+        }
+
+        async fn login(&self) -> miette::Result<String> {
+            Ok(self.login.clone())
+        }
+
+        async fn comments(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            _pr: u64,
+        ) -> miette::Result<Vec<Comment>> {
+            // unwrap: nothing panics while holding the lock.
+            Ok(self.comments.lock().unwrap().clone())
+        }
+
+        async fn create_comment(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            _pr: u64,
+            body: &str,
+        ) -> miette::Result<()> {
+            let mut comments = self.comments.lock().unwrap();
+            let id = comments.len() as u64 + 1;
+            comments.push(Comment {
+                id,
+                author: self.login.clone(),
+                body: body.to_owned(),
+            });
+            Ok(())
+        }
+
+        async fn update_comment(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            id: u64,
+            body: &str,
+        ) -> miette::Result<()> {
+            let mut comments = self.comments.lock().unwrap();
+            let Some(comment) = comments.iter_mut().find(|c| c.id == id) else {
+                miette::bail!("no comment with id {id}");
+            };
+            comment.body = body.to_owned();
+            Ok(())
+        }
+
+        async fn pr_head(&self, _owner: &str, _repo: &str, pr: u64) -> miette::Result<String> {
+            self.heads
+                .get(&pr)
+                .cloned()
+                .ok_or_else(|| miette::miette!("no PR #{pr}"))
+        }
+
+        async fn create_check_run(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            sha: &str,
+            run: &CheckRun,
+            annotations: Vec<Annotation>,
+        ) -> miette::Result<u64> {
+            let mut runs = self.check_runs.lock().unwrap();
+            runs.push(FakeCheckRun {
+                sha: sha.to_owned(),
+                run: run.clone(),
+                annotations,
+            });
+            Ok(runs.len() as u64)
+        }
+
+        async fn add_annotations(
+            &self,
+            _owner: &str,
+            _repo: &str,
+            id: u64,
+            _run: &CheckRun,
+            annotations: Vec<Annotation>,
+        ) -> miette::Result<()> {
+            let mut runs = self.check_runs.lock().unwrap();
+            let Some(existing) = runs.get_mut(id as usize - 1) else {
+                miette::bail!("no check run with id {id}");
+            };
+            existing.annotations.extend(annotations);
+            Ok(())
+        }
+    }
+}
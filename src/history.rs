@@ -0,0 +1,146 @@
+//! Reading the history of the index repository.
+//!
+//! The index that the package manager downloads is a shallow copy, so this
+//! needs a separate local clone of the index repository with its full history.
+
+use std::path::Path;
+
+use gix::ObjectId;
+use miette::IntoDiagnostic as _;
+
+/// A commit that changed a package's index file.
+#[derive(Debug)]
+pub struct Publication {
+    pub commit: ObjectId,
+}
+
+#[derive(Clone)]
+pub struct IndexHistory {
+    repo: gix::ThreadSafeRepository,
+    /// The commit that we treat as the current state of the index. Only
+    /// commits that are reachable from here count as merged.
+    base: ObjectId,
+}
+
+impl IndexHistory {
+    pub fn open(path: &Path, base: &str) -> miette::Result<Self> {
+        let repo = gix::open(path).into_diagnostic()?;
+        let base = repo
+            .rev_parse_single(base)
+            .into_diagnostic()?
+            .object()
+            .into_diagnostic()?
+            .peel_to_commit()
+            .into_diagnostic()?
+            .id;
+        Ok(Self {
+            repo: repo.into_sync(),
+            base,
+        })
+    }
+
+    /// Finds all the merged commits that changed the index file at `index_path`
+    /// (relative to the root of the index), newest first.
+    ///
+    /// Walking the history can take a while, so it happens on a blocking thread.
+    pub async fn publications(&self, index_path: &str) -> miette::Result<Vec<Publication>> {
+        let history = self.clone();
+        let index_path = index_path.to_owned();
+        tokio::task::spawn_blocking(move || history.walk(&index_path))
+            .await
+            .into_diagnostic()?
+    }
+
+    /// A commit only counts as changing the file if its version of the file
+    /// differs from that of every parent. Otherwise, it's a merge that took
+    /// the file from one of its parents, and the commit that actually changed
+    /// it gets visited on its own.
+    fn walk(&self, index_path: &str) -> miette::Result<Vec<Publication>> {
+        let repo = self.repo.to_thread_local();
+        let blob_at = |commit: &gix::Commit<'_>| -> miette::Result<Option<ObjectId>> {
+            let entry = commit
+                .tree()
+                .into_diagnostic()?
+                .lookup_entry_by_path(index_path)
+                .into_diagnostic()?;
+            Ok(entry.map(|e| e.object_id()))
+        };
+
+        let mut ret = Vec::new();
+        let walk = repo
+            .find_commit(self.base)
+            .into_diagnostic()?
+            .ancestors()
+            .all()
+            .into_diagnostic()?;
+        for info in walk {
+            let commit = info.into_diagnostic()?.object().into_diagnostic()?;
+            let Some(blob) = blob_at(&commit)? else {
+                continue;
+            };
+            let mut from_parent = false;
+            for parent in commit.parent_ids() {
+                let parent = parent.object().into_diagnostic()?.into_commit();
+                if blob_at(&parent)? == Some(blob) {
+                    from_parent = true;
+                    break;
+                }
+            }
+
+            if !from_parent {
+                ret.push(Publication { commit: commit.id });
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gix::{actor::SignatureRef, object::tree::EntryKind};
+
+    use super::*;
+    use crate::test_repo::{commit, init};
+
+    #[tokio::test]
+    async fn test_publications() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init(dir.path());
+        let path = "github/org/pkg";
+
+        let first = commit(&repo, "123+alice@users.noreply.github.com", path, "v1\n");
+        commit(&repo, "bob@example.com", "github/org/other", "v1\n");
+        let second = commit(&repo, "carol@example.com", path, "v1\nv2\n");
+
+        // A branch that also changed the file, and a merge that takes the
+        // file from it. The merge itself didn't publish anything.
+        let tree = repo.find_commit(first).unwrap().tree_id().unwrap();
+        let mut editor = repo.edit_tree(tree).unwrap();
+        let blob = repo.write_blob("v1\nx\n").unwrap();
+        editor.upsert(path, EntryKind::Blob, blob).unwrap();
+        let tree = editor.write().unwrap();
+        let side = repo
+            .commit("refs/heads/side", "side", tree, [first])
+            .unwrap()
+            .detach();
+        let sig = SignatureRef {
+            name: "merger".into(),
+            email: "merger@example.com".into(),
+            time: Default::default(),
+        };
+        repo.commit_as(sig, sig, "HEAD", "merge", tree, [second, side])
+            .unwrap();
+
+        let base = commit(&repo, "dave@example.com", ".github/ci.yaml", "\n");
+        // Not merged yet, as far as we're concerned.
+        commit(&repo, "eve@example.com", path, "v1\nv2\nv3\n");
+
+        let history = IndexHistory::open(dir.path(), &base.to_string()).unwrap();
+        let publications = history.publications(path).await.unwrap();
+        let commits: Vec<_> = publications.iter().map(|p| p.commit).collect();
+        assert_eq!(commits, [second, side, first]);
+
+        let nope = history.publications("github/org/nope").await.unwrap();
+        assert!(nope.is_empty());
+    }
+}
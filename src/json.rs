@@ -0,0 +1,113 @@
+//! The machine-readable version of our report.
+//!
+//! The report is a JSON object of the form
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "good": true,
+//!   "invalid_diff": null,
+//!   "items": [...]
+//! }
+//! ```
+//!
+//! `invalid_diff` is an error message if we couldn't parse the diff at all, in
+//! which case `items` is empty. Each element of `items` has a `"kind"`
+//! and a `"good"` field, and the rest depends on the kind:
+//!
+//! - `"package"` items describe a submitted package. They have a `"package"`
+//!   field (the index entry, in the same format as the index itself), the
+//!   `"index_path"` and `"line"` where that entry was added, a
+//!   `"format_check"` field saying whether the entry is formatted exactly the
+//!   way the package manager writes it (and if not, what it should be), a
+//!   `"permission"` field (`null` if permissions weren't checked; otherwise
+//!   `"granted_by"` says which rule allowed the submitter, if any), a
+//!   `"version_check"` field comparing the version to previously published
+//!   ones, a `"provenance"` field saying whether the package's commit is
+//!   tagged or on the default branch (`null` if the package couldn't be
+//!   fetched), and a `"status"` field describing the outcome of fetching the
+//!   package, locating it within its repository, and checking its manifest.
+//! - `"yank"` items describe a package version that was yanked. Like
+//!   `"package"` items, they have `"package"`, `"index_path"`, `"line"` and
+//!   `"permission"` fields, and also a `"dependents"` field listing the index
+//!   entries of the packages that depend on the yanked version.
+//! - `"path"` items describe a change to a file outside the index, or a change
+//!   that index files can't have. They have a `"path"` field and a `"change"`
+//!   field: one of `"added"`, `"modified"`, `"renamed"` (with the old path in
+//!   `"from"`), `"deleted"`, `"binary"`, `"no_lines"`, `"not_a_file"` (with a
+//!   `"file_type"` of `"symlink"` or `"submodule"`), or `"mode_changed"` (with
+//!   `"old_mode"` and `"new_mode"`). Changes to ownership files also have a
+//!   `"permissions"` field, with a `"permission"` for each package whose
+//!   ownership file changed; it's missing if permissions weren't checked.
+//! - `"invalid_change"` items describe a change to an index file that we
+//!   couldn't make sense of. They have an `"error"` field with a message.
+//!
+//! Apart from the index entry in `"package"`, versions and package ids are
+//! rendered as strings, in the same format as the human-readable report.
+//!
+//! Any change that could break a consumer of this format, like removing or
+//! renaming a field or changing what a value means, must come with a bump of
+//! [`SCHEMA_VERSION`]. New fields, item kinds and values can be added without
+//! one, so consumers should ignore the ones that they don't know about.
+
+use nickel_lang_package::{
+    IndexDependency,
+    index::{Package, serialize::PackageFormat},
+};
+use serde::{Serialize, Serializer};
+
+/// The version of the JSON report format.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct JsonReport {
+    pub schema_version: u32,
+    pub good: bool,
+    pub invalid_diff: Option<String>,
+    pub items: Vec<JsonItem>,
+}
+
+#[derive(Serialize)]
+pub struct JsonItem {
+    pub good: bool,
+    #[serde(flatten)]
+    pub item: serde_json::Value,
+}
+
+/// Serializes something using its `Display` impl.
+pub fn display<T: std::fmt::Display, S: Serializer>(t: &T, ser: S) -> Result<S::Ok, S::Error> {
+    ser.collect_str(t)
+}
+
+/// Serializes a sequence using the `Display` impls of its elements.
+pub fn display_seq<T: std::fmt::Display, S: Serializer>(
+    ts: &[T],
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    ser.collect_seq(ts.iter().map(ToString::to_string))
+}
+
+/// Serializes a package in the index format.
+pub fn package<S: Serializer>(pkg: &Package, ser: S) -> Result<S::Ok, S::Error> {
+    PackageFormat::from(pkg.clone()).serialize(ser)
+}
+
+/// Serializes a list of packages in the index format.
+pub fn packages<S: Serializer>(pkgs: &[Package], ser: S) -> Result<S::Ok, S::Error> {
+    ser.collect_seq(pkgs.iter().map(|pkg| PackageFormat::from(pkg.clone())))
+}
+
+/// Serializes a dependency as its id and version requirement.
+pub fn index_dependency<S: Serializer>(dep: &IndexDependency, ser: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Dep {
+        id: String,
+        req: String,
+    }
+
+    Dep {
+        id: dep.id.to_string(),
+        req: dep.version.to_string(),
+    }
+    .serialize(ser)
+}
@@ -0,0 +1,214 @@
+//! License checks for submitted packages.
+
+use std::path::Path;
+
+use serde::Serialize;
+use spdx::{Expression, LicenseItem, LicenseReq};
+
+/// Which licenses we accept.
+#[derive(Clone, Debug, Default)]
+pub enum LicensePolicy {
+    /// Any valid SPDX license expression.
+    #[default]
+    Any,
+    /// Expressions that can be satisfied using only OSI-approved licenses.
+    OsiApproved,
+    /// Expressions that can be satisfied using only licenses with these SPDX ids.
+    AllowList(Vec<String>),
+}
+
+impl LicensePolicy {
+    fn allows(&self, req: &LicenseReq) -> bool {
+        match (self, &req.license) {
+            (LicensePolicy::Any, _) => true,
+            (LicensePolicy::OsiApproved, LicenseItem::Spdx { id, .. }) => id.is_osi_approved(),
+            (LicensePolicy::AllowList(ids), LicenseItem::Spdx { id, .. }) => {
+                ids.iter().any(|allowed| allowed == id.name)
+            }
+            // Custom `LicenseRef`s never appear on an allowlist.
+            (_, LicenseItem::Other { .. }) => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            LicensePolicy::Any => "any license".to_owned(),
+            LicensePolicy::OsiApproved => "OSI-approved licenses".to_owned(),
+            LicensePolicy::AllowList(ids) => ids.join(", "),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "verdict", content = "details", rename_all = "snake_case")]
+enum ExpressionCheck {
+    Valid,
+    Empty,
+    /// The license isn't a valid SPDX expression. Contains the parse error.
+    Invalid(String),
+    /// The license is valid, but it isn't allowed by our policy. Contains the
+    /// description of the policy.
+    NotAllowed(String),
+}
+
+#[derive(Serialize)]
+pub struct LicenseChecks {
+    license: String,
+    expression: ExpressionCheck,
+    /// The path (relative to the repository root) of the license file that we found.
+    license_file: Option<String>,
+}
+
+/// The names of the files that we accept as license files.
+fn is_license_file_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["LICENSE", "LICENCE", "COPYING"].iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '-', '_']))
+    })
+}
+
+fn find_license_file(dir: &Path) -> Option<String> {
+    let mut names: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            (is_license_file_name(&name) && entry.path().is_file()).then_some(name)
+        })
+        .collect();
+    // Sort so that our choice is deterministic if there are several.
+    names.sort();
+    names.into_iter().next()
+}
+
+impl LicenseChecks {
+    /// Checks a package's license expression, and looks for a license file.
+    ///
+    /// We look for the license file in the package directory first, and then
+    /// at the root of the repository (because packages in subdirectories of a
+    /// monorepo often share a license file).
+    pub fn new(
+        license: &str,
+        repo_root: &Path,
+        package_dir: &Path,
+        policy: &LicensePolicy,
+    ) -> Self {
+        let expression = if license.trim().is_empty() {
+            ExpressionCheck::Empty
+        } else {
+            match Expression::parse(license) {
+                Err(e) => ExpressionCheck::Invalid(e.to_string()),
+                Ok(expr) if !expr.evaluate(|req| policy.allows(req)) => {
+                    ExpressionCheck::NotAllowed(policy.describe())
+                }
+                Ok(_) => ExpressionCheck::Valid,
+            }
+        };
+
+        let license_file = [package_dir, repo_root].iter().find_map(|dir| {
+            let name = find_license_file(dir)?;
+            let path = dir.join(name);
+            let relative = path.strip_prefix(repo_root).unwrap_or(&path);
+            Some(relative.display().to_string())
+        });
+
+        Self {
+            license: license.to_owned(),
+            expression,
+            license_file,
+        }
+    }
+
+    pub fn is_good(&self) -> bool {
+        matches!(self.expression, ExpressionCheck::Valid) && self.license_file.is_some()
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        let license = &self.license;
+        match &self.expression {
+            ExpressionCheck::Valid => writeln!(f, "{indent}✅ license `{license}` is acceptable")?,
+            ExpressionCheck::Empty => writeln!(f, "{indent}❌ no license specified")?,
+            ExpressionCheck::Invalid(e) => writeln!(
+                f,
+                "{indent}❌ license `{license}` isn't a valid SPDX expression: {e}"
+            )?,
+            ExpressionCheck::NotAllowed(policy) => writeln!(
+                f,
+                "{indent}❌ license `{license}` isn't allowed: we accept {policy}"
+            )?,
+        }
+
+        match &self.license_file {
+            Some(path) => writeln!(f, "{indent}✅ found license file {path}"),
+            None => writeln!(f, "{indent}❌ couldn't find a LICENSE file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_expression(license: &str, policy: &LicensePolicy) -> ExpressionCheck {
+        let dir = tempfile::tempdir().unwrap();
+        LicenseChecks::new(license, dir.path(), dir.path(), policy).expression
+    }
+
+    #[test]
+    fn test_expressions() {
+        let any = LicensePolicy::Any;
+        let osi = LicensePolicy::OsiApproved;
+        let allow = LicensePolicy::AllowList(vec!["MIT".to_owned()]);
+
+        assert!(matches!(check_expression("", &any), ExpressionCheck::Empty));
+        assert!(matches!(
+            check_expression("MIT OR", &any),
+            ExpressionCheck::Invalid(_)
+        ));
+        assert!(matches!(
+            check_expression("Not-A-License", &any),
+            ExpressionCheck::Invalid(_)
+        ));
+        assert!(matches!(
+            check_expression("MIT OR Apache-2.0", &any),
+            ExpressionCheck::Valid
+        ));
+        assert!(matches!(
+            check_expression("MIT OR Apache-2.0", &osi),
+            ExpressionCheck::Valid
+        ));
+        assert!(matches!(
+            check_expression("CC-BY-4.0", &osi),
+            ExpressionCheck::NotAllowed(_)
+        ));
+        assert!(matches!(
+            check_expression("MIT OR Apache-2.0", &allow),
+            ExpressionCheck::Valid
+        ));
+        assert!(matches!(
+            check_expression("MIT AND Apache-2.0", &allow),
+            ExpressionCheck::NotAllowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_license_file() {
+        let repo = tempfile::tempdir().unwrap();
+        let pkg_dir = repo.path().join("lib");
+        std::fs::create_dir(&pkg_dir).unwrap();
+        let check = || LicenseChecks::new("MIT", repo.path(), &pkg_dir, &LicensePolicy::Any);
+
+        assert!(!check().is_good());
+
+        std::fs::write(repo.path().join("LICENSE-MIT"), "").unwrap();
+        assert_eq!(check().license_file.as_deref(), Some("LICENSE-MIT"));
+
+        // Prefer the license in the package's own directory.
+        std::fs::write(pkg_dir.join("License.md"), "").unwrap();
+        assert_eq!(check().license_file.as_deref(), Some("lib/License.md"));
+        assert!(check().is_good());
+
+        assert!(!is_license_file_name("LICENSES_ARE_COOL.txt"));
+    }
+}
@@ -1,19 +1,194 @@
-use clap::Parser;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read as _,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
 use gitpatch::Patch;
 use miette::{IntoDiagnostic, bail};
 use nickel_lang_package::{
     config::Config,
-    index::{Package, PackageIndex, PreciseId, Shared},
+    index::{Id, Package, PackageIndex, PreciseId, Shared},
+    version::SemVer,
 };
-use octocrab::Octocrab;
+use serde::Serialize;
 use tempfile::tempdir;
+use tokio::{sync::Semaphore, task::JoinHandle};
 
-use crate::package::{IntoDiagnostic as _, ManifestChecks};
+use crate::diff::FileChange;
+use crate::eval::EvalLimits;
+use crate::github::{Github, GithubClient};
+use crate::history::IndexHistory;
+use crate::license::LicensePolicy;
+use crate::owners::Owners;
+use crate::package::{
+    AddedPackage, Change, CheckConfig, FormatCheck, IntoDiagnostic as _, ManifestChecks,
+    SubdirError, VersionCheck, YankedPackage,
+};
+use crate::provenance::ProvenanceCheck;
+use crate::tree_diff::TreeDiff;
 
+mod app;
+mod check_run;
+mod comment;
+mod diff;
+mod eval;
+mod github;
+mod history;
+mod json;
+mod license;
+mod owners;
 mod package;
+mod provenance;
+#[cfg(test)]
+mod test_repo;
+mod tree_diff;
 
 #[derive(Parser)]
 struct Args {
+    /// Check a diff read from this file (or `-` for stdin) instead of a PR.
+    ///
+    /// This doesn't talk to the github API at all: permissions aren't checked
+    /// and the report is only printed.
+    #[arg(
+        long,
+        required_unless_present_any = ["PrArgs", "eval_package"],
+        conflicts_with = "PrArgs"
+    )]
+    diff_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pr: Option<PrArgs>,
+
+    /// The format of the report that we print.
+    ///
+    /// This doesn't affect the comment that we post to the PR, which is always markdown.
+    #[arg(long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+
+    /// Write the report to this file instead of printing it to stdout.
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Accept versions older than the newest published one, provided that
+    /// they're patch releases of a published minor version and the newest on
+    /// their own semver-compatible line (e.g. a 1.2.4 release after 2.0.0 has
+    /// been published, if 1.2.3 was the newest 1.x release).
+    #[arg(long)]
+    allow_backports: bool,
+
+    /// Only accept licenses that are approved by the OSI.
+    #[arg(long, conflicts_with = "allowed_licenses")]
+    osi_approved_only: bool,
+
+    /// Only accept these licenses (as a comma-separated list of SPDX ids).
+    #[arg(long, value_delimiter = ',')]
+    allowed_licenses: Option<Vec<String>>,
+
+    /// The latest released version of Nickel. Packages with a newer
+    /// `minimal_nickel_version` are rejected.
+    ///
+    /// The default is bumped with each customs release; this overrides it
+    /// if Nickel has been released since.
+    #[arg(long, default_value_t = package::LATEST_NICKEL_VERSION)]
+    latest_nickel_version: SemVer,
+
+    /// How long to let a package's entry point evaluate before giving up, in seconds.
+    #[arg(long, default_value_t = eval::DEFAULT_TIMEOUT_SECS)]
+    eval_timeout: u64,
+
+    /// How much memory a package's entry point can use while evaluating, in MiB.
+    #[arg(long, default_value_t = eval::DEFAULT_MEMORY_LIMIT_MIB)]
+    eval_memory_limit: u64,
+
+    /// Fetch the index, packages and their dependencies from a mirror of
+    /// github instead of from github. This is the URL that stands in for
+    /// `https://github.com`: for example, with `file:///srv/mirrors`, the
+    /// package `github:org/name` is fetched from `file:///srv/mirrors/org/name`.
+    ///
+    /// Reports still refer to packages by their github ids.
+    #[arg(long)]
+    git_mirror: Option<String>,
+
+    /// Require a tag named `v<version>` or `<version>` to point at each
+    /// package's commit. Without this, commits on the repository's default
+    /// branch are also accepted, and other commits just get a warning.
+    #[arg(long)]
+    require_tag: bool,
+
+    /// How many packages to fetch and check at once.
+    #[arg(long, default_value = "4")]
+    jobs: NonZeroUsize,
+
+    /// A local clone of the index repository, with its history.
+    ///
+    /// If this is provided, people who published earlier versions of a
+    /// package are allowed to publish new ones.
+    #[arg(long)]
+    index_repo: Option<PathBuf>,
+
+    /// The revision of `--index-repo` that the PR is based on. Only changes
+    /// that are reachable from here count as published.
+    #[arg(long, default_value = "HEAD")]
+    index_base: String,
+
+    /// Read the PR's changes from `--index-repo` instead of asking github for
+    /// its diff, by comparing the tree of this revision (the PR's head) with
+    /// the tree of its merge base with `--index-base`.
+    #[arg(long, requires_all = ["PrArgs", "index_repo"])]
+    head: Option<String>,
+
+    /// Evaluate the package in this directory and print the result. This is
+    /// how we run evaluation in a child process; see the `eval` module.
+    #[arg(long, hide = true, conflicts_with_all = ["diff_file", "PrArgs"])]
+    eval_package: Option<PathBuf>,
+}
+
+impl Args {
+    fn check_config(&self) -> CheckConfig {
+        let licenses = if self.osi_approved_only {
+            LicensePolicy::OsiApproved
+        } else if let Some(ids) = &self.allowed_licenses {
+            LicensePolicy::AllowList(ids.clone())
+        } else {
+            LicensePolicy::Any
+        };
+        CheckConfig {
+            allow_backports: self.allow_backports,
+            licenses,
+            latest_nickel_version: self.latest_nickel_version.clone(),
+            eval: EvalLimits {
+                timeout: Duration::from_secs(self.eval_timeout),
+                memory_limit_mib: self.eval_memory_limit,
+            },
+            require_tag: self.require_tag,
+            jobs: self.jobs,
+        }
+    }
+
+    /// Where the package manager keeps the index and fetches things from.
+    fn index_config(&self) -> miette::Result<Config> {
+        let config = Config::new().into_diag()?;
+        match &self.git_mirror {
+            Some(mirror) => package::with_mirror(config, mirror),
+            None => Ok(config),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Markdown,
+    /// See the `json` module for a description of the schema.
+    Json,
+}
+
+#[derive(clap::Args)]
+struct PrArgs {
     #[arg(long)]
     owner: String,
 
@@ -26,11 +201,71 @@ struct Args {
     #[arg(long)]
     pr: u64,
 
-    #[arg(long)]
+    #[arg(long, conflicts_with = "app_id")]
     token: Option<String>,
+
+    /// Authenticate as the github app with this ID, instead of with a token.
+    ///
+    /// The app needs to be installed on the index repository. Installing it on
+    /// packages' orgs as well lets us see private org members and repository
+    /// collaborators.
+    #[arg(long, requires = "app_private_key")]
+    app_id: Option<u64>,
+
+    /// A file containing the github app's private key, in PEM format.
+    #[arg(long, requires = "app_id")]
+    app_private_key: Option<PathBuf>,
+
+    /// Check the PR as usual, but don't write anything back to github.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// When updating our comment, keep the previous reports in a collapsed section.
+    #[arg(long)]
+    keep_history: bool,
+
+    /// Also publish the report as a check run on the PR's head commit.
+    ///
+    /// This requires a token with write access to checks.
+    #[arg(long)]
+    check_run: bool,
+}
+
+/// The person who submitted a PR, along with a client for asking github about them.
+struct Submitter<'a, G> {
+    github: &'a G,
+    user: &'a str,
+    /// The github org or user that owns the index repository.
+    index_owner: &'a str,
+    /// The name of the index repository.
+    index_repo: &'a str,
+    /// The history of the index, if we have it.
+    history: Option<&'a IndexHistory>,
+}
+
+/// The rule that gave someone permission to submit a package.
+#[derive(Serialize)]
+#[serde(tag = "rule", content = "team", rename_all = "snake_case")]
+pub enum Grant {
+    /// The submitter is the github user that owns the package's repository.
+    RepoOwner,
+    /// The submitter is listed in the package's ownership file.
+    ListedOwner,
+    /// The submitter is a member of the org that owns the package's repository.
+    ///
+    /// Without a github app installed on the org, we can only see public members.
+    OrgMember,
+    /// The submitter is a collaborator on the package's repository. We can only
+    /// check this with a github app installed on the repository.
+    Collaborator,
+    /// The submitter is a member of a team that's listed in the package's ownership file.
+    ListedTeam(String),
+    /// The submitter published an earlier version of the package.
+    PreviousPublisher,
 }
 
 /// Someone submitted a package to us. Do we think it's "their" package?
+#[derive(Serialize)]
 pub struct Permission {
     /// The user that submitted the package.
     user: String,
@@ -40,30 +275,173 @@ pub struct Permission {
     repo: String,
     /// Do we think they're allowed?
     is_allowed: bool,
+    /// If they're allowed, the rule that allowed them.
+    granted_by: Option<Grant>,
 }
 
 impl Permission {
+    /// Checks whether the submitter (if we know who it is) can touch `pkg`.
+    ///
+    /// `index_dir` is the (pre-PR) index, which we look for ownership files in.
+    async fn for_package(
+        submitter: Option<&Submitter<'_, impl Github>>,
+        index_dir: &Path,
+        pkg: &Package,
+    ) -> miette::Result<Option<Self>> {
+        let PreciseId::Github { org, name, .. } = &pkg.id;
+        // unwrap: package paths are made of strings, so they're always UTF-8.
+        let index_path = Id::from(pkg.id.clone()).path().to_str().unwrap().to_owned();
+        Self::for_index_path(submitter, index_dir, org, name, &index_path).await
+    }
+
+    /// Checks whether the submitter (if we know who it is) can touch the
+    /// package in `org/repo` whose index file is at `index_path`.
+    async fn for_index_path(
+        submitter: Option<&Submitter<'_, impl Github>>,
+        index_dir: &Path,
+        org: &str,
+        repo: &str,
+        index_path: &str,
+    ) -> miette::Result<Option<Self>> {
+        let Some(s) = submitter else {
+            return Ok(None);
+        };
+        let owners = Owners::load(index_dir, Path::new(index_path))?.unwrap_or_default();
+        let perm = Self::check(s, org.to_owned(), repo.to_owned(), &owners, index_path);
+        Ok(Some(perm.await?))
+    }
+
     async fn check(
-        client: &Octocrab,
-        user: String,
+        s: &Submitter<'_, impl Github>,
         org: String,
         repo: String,
+        owners: &Owners,
+        index_path: &str,
     ) -> miette::Result<Self> {
-        // It might make sense to check `client.repos(..).is_collaborator`, but that requires
-        // authentication (beyond the default github CI token) and we'd prefer not to rely on it.
-        let is_allowed = user == org
-            || client
-                .orgs(&org)
-                .check_membership(&user)
-                .await
-                .into_diagnostic()?;
+        let user = s.user.to_owned();
+        // The cheap checks go first, so that we only ask github if we need to.
+        // Like everywhere else on github, logins are case-insensitive.
+        if user.eq_ignore_ascii_case(&org) {
+            return Ok(Self::granted(user, org, repo, Grant::RepoOwner));
+        } else if owners.has_user(&user) {
+            return Ok(Self::granted(user, org, repo, Grant::ListedOwner));
+        }
+
+        // If our app is installed on the package's repository, its installation
+        // can see private org members and collaborators.
+        let installation = s.github.installation(&org, &repo).await?;
+        let github = installation.as_ref().unwrap_or(s.github);
+        let granted_by = if github.is_org_member(&org, &user).await? {
+            Some(Grant::OrgMember)
+        } else if installation.is_some() && github.is_collaborator(&org, &repo, &user).await? {
+            Some(Grant::Collaborator)
+        } else {
+            let mut team_grant = None;
+            for team in &owners.teams {
+                if owners::is_team_member(github, team, &user).await? {
+                    team_grant = Some(Grant::ListedTeam(team.clone()));
+                    break;
+                }
+            }
+            match team_grant {
+                Some(grant) => Some(grant),
+                None => is_previous_publisher(s, index_path)
+                    .await?
+                    .then_some(Grant::PreviousPublisher),
+            }
+        };
         Ok(Self {
-            is_allowed,
+            is_allowed: granted_by.is_some(),
+            granted_by,
             user,
             org,
             repo,
         })
     }
+
+    fn granted(user: String, org: String, repo: String, grant: Grant) -> Self {
+        Self {
+            user,
+            org,
+            repo,
+            is_allowed: true,
+            granted_by: Some(grant),
+        }
+    }
+}
+
+/// Did the submitter publish an earlier version of the package whose index
+/// file is at `index_path`?
+async fn is_previous_publisher(
+    s: &Submitter<'_, impl Github>,
+    index_path: &str,
+) -> miette::Result<bool> {
+    let Some(history) = s.history else {
+        return Ok(false);
+    };
+    // Anyone can put anyone's email address in a commit, even one of github's
+    // noreply addresses, so we go by who opened the PR that merged the commit.
+    for p in history.publications(index_path).await? {
+        let authors = s
+            .github
+            .pr_authors(s.index_owner, s.index_repo, &p.commit.to_string())
+            .await?;
+        if authors
+            .iter()
+            .any(|login| login.eq_ignore_ascii_case(s.user))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Writes the line of a report that says whether the submitter is allowed to
+/// touch the package. `permission` is `None` if we didn't check.
+fn format_permission(
+    f: &mut std::fmt::Formatter,
+    indent_spaces: &str,
+    permission: Option<&Permission>,
+) -> std::fmt::Result {
+    let Some(perm) = permission else {
+        return writeln!(
+            f,
+            "{indent_spaces}*⚠️ didn't check permissions, because this is a local diff"
+        );
+    };
+    let Permission {
+        user, org, repo, ..
+    } = perm;
+    match &perm.granted_by {
+        Some(Grant::RepoOwner) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, the owner of {org}/{repo}"
+        ),
+        Some(Grant::ListedOwner) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, a listed owner of the package"
+        ),
+        Some(Grant::OrgMember) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, a member of {org}"
+        ),
+        Some(Grant::Collaborator) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, a collaborator on {org}/{repo}"
+        ),
+        Some(Grant::PreviousPublisher) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, who published earlier versions of the package"
+        ),
+        Some(Grant::ListedTeam(team)) => writeln!(
+            f,
+            "{indent_spaces}*✅ this PR is by {user}, a member of {team}, which is a listed owner of the package"
+        ),
+        None => writeln!(
+            f,
+            "{indent_spaces}*❌ this PR is by {user}, who is not a member of {org} or a listed owner of the package"
+        ),
+    }
 }
 
 enum Report {
@@ -71,6 +449,18 @@ enum Report {
     PackageReports(Vec<Box<dyn ReportItem>>),
 }
 
+#[cfg(test)]
+impl From<Vec<PathReport>> for Report {
+    fn from(reports: Vec<PathReport>) -> Self {
+        Report::PackageReports(
+            reports
+                .into_iter()
+                .map(|r| Box::new(r) as Box<dyn ReportItem>)
+                .collect(),
+        )
+    }
+}
+
 impl Report {
     fn is_good(&self) -> bool {
         match self {
@@ -78,6 +468,36 @@ impl Report {
             Report::PackageReports(package_reports) => package_reports.iter().all(|r| r.is_good()),
         }
     }
+
+    fn to_json(&self) -> json::JsonReport {
+        let (invalid_diff, items) = match self {
+            Report::InvalidDiff(e) => (Some(e.to_string()), Vec::new()),
+            Report::PackageReports(package_reports) => (
+                None,
+                package_reports
+                    .iter()
+                    .map(|r| json::JsonItem {
+                        good: r.is_good(),
+                        item: r.to_json(),
+                    })
+                    .collect(),
+            ),
+        };
+        json::JsonReport {
+            schema_version: json::SCHEMA_VERSION,
+            good: self.is_good(),
+            invalid_diff,
+            items,
+        }
+    }
+
+    /// Renders this report in the given format.
+    fn render(&self, format: Format) -> miette::Result<String> {
+        match format {
+            Format::Markdown => Ok(self.to_string()),
+            Format::Json => serde_json::to_string_pretty(&self.to_json()).into_diagnostic(),
+        }
+    }
 }
 
 impl std::fmt::Display for Report {
@@ -97,51 +517,173 @@ impl std::fmt::Display for Report {
 trait ReportItem {
     fn is_good(&self) -> bool;
     fn format_with_indent(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result;
+    /// Converts this item to JSON, for the machine-readable report.
+    fn to_json(&self) -> serde_json::Value;
+
+    /// If this item failed and we know which index file (and line) it's about,
+    /// returns the index file path and line number.
+    fn failure_location(&self) -> Option<(&str, u64)> {
+        None
+    }
 }
 
+/// Displays a single report item, without any indentation.
+struct DisplayItem<'a>(&'a dyn ReportItem);
+
+impl std::fmt::Display for DisplayItem<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.format_with_indent(f, "")
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename = "package")]
 struct PackageReport {
+    #[serde(rename = "package", serialize_with = "json::package")]
     pkg: Package,
-    permission: Permission,
+    /// The path of the index file that the package was added to.
+    index_path: String,
+    /// The line number of the package's entry in the index file.
+    line: u64,
+    format_check: FormatCheck,
+    /// This is `None` if we're checking a local diff, and so there's nobody to check.
+    permission: Option<Permission>,
+    version_check: VersionCheck,
+    /// Where the package's commit comes from. This is `None` if we couldn't fetch the package.
+    provenance: Option<ProvenanceCheck>,
     status: PackageStatus,
 }
 
 impl PackageReport {
     async fn new(
-        client: &Octocrab,
-        user: &str,
+        submitter: Option<&Submitter<'_, impl Github>>,
         index: &PackageIndex<Shared>,
-        pkg: Package,
+        index_config: &Config,
+        added: AddedPackage,
+        also_published: &[SemVer],
+        config: &CheckConfig,
+        fetched: FetchedChecks,
     ) -> miette::Result<Self> {
-        let PreciseId::Github {
-            org, name, path, ..
-        } = &pkg.id;
-        let permission =
-            Permission::check(client, user.to_owned(), org.clone(), name.clone()).await?;
+        let AddedPackage {
+            pkg,
+            path: index_path,
+            line,
+            format_check,
+        } = added;
+        let permission = Permission::for_package(submitter, &index_config.index_dir, &pkg).await?;
+        let version_check = package::check_version(&pkg, index, also_published, config)?;
 
+        Ok(Self {
+            pkg,
+            index_path,
+            line,
+            format_check,
+            permission,
+            version_check,
+            provenance: fetched.provenance,
+            status: fetched.status,
+        })
+    }
+}
+
+/// The checks that need a copy of the package. These block on git and on
+/// evaluating nickel, so we run them off the async runtime.
+struct FetchedChecks {
+    provenance: Option<ProvenanceCheck>,
+    status: PackageStatus,
+}
+
+impl FetchedChecks {
+    fn new(pkg: &Package, index_config: &Config, config: &CheckConfig) -> miette::Result<Self> {
+        // The index can't be shared between threads, so each check opens its own.
+        let index = PackageIndex::shared(index_config.clone()).into_diag()?;
+        let PreciseId::Github { path, .. } = &pkg.id;
         let temp_dir = tempdir().into_diagnostic()?;
-        let status = if let Err(e) = package::fetch(&pkg, temp_dir.path()) {
+        // The package directory is canonical, so the root has to be too for
+        // paths inside it to be reported relative to the root. (On macOS, for
+        // example, the temporary directory is behind a symlink.)
+        let repo_root = &temp_dir.path().canonicalize().into_diagnostic()?;
+        let mut provenance = None;
+        let status = if let Err(e) = package::fetch(pkg, repo_root, index_config) {
             PackageStatus::FetchFailed(e.to_string())
         } else {
-            let path = temp_dir.path().join(path);
-            match package::check_manifest(&pkg, &path, index) {
-                Ok(c) => PackageStatus::Manifest(Box::new(c)),
-                Err(e) => PackageStatus::EvalFailed(e.to_string()),
+            provenance = Some(ProvenanceCheck::new(pkg, index_config, config.require_tag));
+            match package::package_dir(repo_root, path.as_ref()) {
+                Err(e) => PackageStatus::BadSubdir(e),
+                Ok(package_dir) => {
+                    match package::check_manifest(
+                        pkg,
+                        repo_root,
+                        &package_dir,
+                        &index,
+                        index_config,
+                        config,
+                    ) {
+                        Ok(c) => PackageStatus::Manifest(Box::new(c)),
+                        Err(e) => PackageStatus::EvalFailed(e.to_string()),
+                    }
+                }
             }
         };
 
-        Ok(Self {
-            pkg,
-            permission,
-            status,
-        })
+        Ok(Self { provenance, status })
+    }
+
+    /// Starts checking a package on a blocking thread, once one of the
+    /// `jobs` permits is free.
+    fn spawn(
+        pkg: Package,
+        index_config: Config,
+        config: CheckConfig,
+        jobs: Arc<Semaphore>,
+    ) -> Task<miette::Result<Self>> {
+        spawn_job(jobs, move || Self::new(&pkg, &index_config, &config))
+    }
+}
+
+/// A spawned task that gets aborted if it's dropped before it finishes, for
+/// example because checking an earlier package failed and we gave up.
+struct Task<T>(JoinHandle<T>);
+
+impl<T> Task<T> {
+    async fn join(mut self) -> miette::Result<T> {
+        (&mut self.0).await.into_diagnostic()
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.0.abort();
     }
 }
 
+/// Starts running `f` on a blocking thread, once one of the `jobs` permits
+/// is free.
+///
+/// Once `f` has started it runs to completion, but aborting the task before
+/// then means that it never starts.
+fn spawn_job<T: Send + 'static>(
+    jobs: Arc<Semaphore>,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Task<T> {
+    Task(tokio::spawn(async move {
+        // unwrap: we never close the semaphore.
+        let _permit = jobs.acquire_owned().await.unwrap();
+        // unwrap: this only fails if `f` panicked, which then fails the task.
+        tokio::task::spawn_blocking(f).await.unwrap()
+    }))
+}
+
 impl ReportItem for PackageReport {
     fn is_good(&self) -> bool {
-        self.permission.is_allowed
+        self.format_check.is_good()
+            && self.permission.as_ref().is_none_or(|p| p.is_allowed)
+            && self.version_check.is_good()
+            && self.provenance.as_ref().is_none_or(|p| p.is_good())
             && match &self.status {
-                PackageStatus::FetchFailed(_) | PackageStatus::EvalFailed(_) => false,
+                PackageStatus::FetchFailed(_)
+                | PackageStatus::BadSubdir(_)
+                | PackageStatus::EvalFailed(_) => false,
                 PackageStatus::Manifest(manifest_checks) => manifest_checks.is_good(),
             }
     }
@@ -150,33 +692,28 @@ impl ReportItem for PackageReport {
         let PreciseId::Github {
             org, name, path, ..
         } = &self.pkg.id;
-        let perm = &self.permission;
         let indent_spaces = " ".repeat(indent.len());
         writeln!(
             f,
             "{}package {org}/{name}/{path}, version {}",
             indent, self.pkg.version
         )?;
-        if perm.is_allowed {
-            writeln!(
-                f,
-                "{indent_spaces}*✅ this PR is by {}, a collaborator on {}/{}",
-                perm.user, perm.org, perm.repo
-            )?;
-        } else {
-            writeln!(
-                f,
-                "{indent_spaces}*❌ this PR is by {}, who is not a public member of {}",
-                perm.user, perm.org
-            )?;
-        };
+        self.format_check.format(f, &format!("{indent_spaces}*"))?;
+        format_permission(f, &indent_spaces, self.permission.as_ref())?;
+        self.version_check
+            .format(f, &format!("{indent_spaces}*"), &self.pkg.version)?;
 
         if let PackageStatus::FetchFailed(e) = &self.status {
             writeln!(f, "{indent_spaces}*❌ failed to fetch package: {e}",)?;
         } else {
             writeln!(f, "{indent_spaces}*✅ fetched package",)?;
+            if let Some(provenance) = &self.provenance {
+                provenance.format(f, &format!("{indent_spaces}*"), &self.pkg.version)?;
+            }
 
-            if let PackageStatus::EvalFailed(e) = &self.status {
+            if let PackageStatus::BadSubdir(e) = &self.status {
+                writeln!(f, "{indent_spaces}*❌ {e}")?;
+            } else if let PackageStatus::EvalFailed(e) = &self.status {
                 writeln!(f, "{indent_spaces}*❌ failed to evaluate manifest: {e}",)?;
             } else {
                 writeln!(f, "{indent_spaces}*✅ evaluated manifest",)?;
@@ -190,12 +727,185 @@ impl ReportItem for PackageReport {
 
         Ok(())
     }
+
+    fn to_json(&self) -> serde_json::Value {
+        // unwrap: all of our maps have string keys, so serialization can't fail.
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn failure_location(&self) -> Option<(&str, u64)> {
+        (!self.is_good()).then_some((self.index_path.as_str(), self.line))
+    }
+}
+
+/// Someone wants to yank a package version from the index.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename = "yank")]
+struct YankReport {
+    #[serde(rename = "package", serialize_with = "json::package")]
+    pkg: Package,
+    /// The path of the index file that the package was yanked in.
+    index_path: String,
+    /// The line of the index file that the package was yanked on.
+    line: u64,
+    permission: Option<Permission>,
+    /// The packages in the index whose dependencies might resolve to the yanked version.
+    #[serde(serialize_with = "json::packages")]
+    dependents: Vec<Package>,
+}
+
+impl YankReport {
+    async fn new(
+        submitter: Option<&Submitter<'_, impl Github>>,
+        index_config: &Config,
+        yanked: YankedPackage,
+    ) -> miette::Result<Self> {
+        let YankedPackage {
+            pkg,
+            path: index_path,
+            line,
+        } = yanked;
+        let permission = Permission::for_package(submitter, &index_config.index_dir, &pkg).await?;
+        let dependents = package::dependents(
+            &index_config.index_dir,
+            &pkg.id.clone().into(),
+            &pkg.version,
+        )?;
+        Ok(Self {
+            pkg,
+            index_path,
+            line,
+            permission,
+            dependents,
+        })
+    }
+}
+
+impl ReportItem for YankReport {
+    fn is_good(&self) -> bool {
+        // Dependents are just for information: yanking is meant for versions
+        // that are broken, whether or not someone depends on them.
+        self.permission.as_ref().is_none_or(|p| p.is_allowed)
+    }
+
+    fn format_with_indent(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        let PreciseId::Github {
+            org, name, path, ..
+        } = &self.pkg.id;
+        let indent_spaces = " ".repeat(indent.len());
+        writeln!(
+            f,
+            "{}yanking package {org}/{name}/{path}, version {}",
+            indent, self.pkg.version
+        )?;
+        format_permission(f, &indent_spaces, self.permission.as_ref())?;
+
+        if self.dependents.is_empty() {
+            writeln!(
+                f,
+                "{indent_spaces}*✅ no packages in the index depend on this version"
+            )?;
+        } else {
+            writeln!(
+                f,
+                "{indent_spaces}*⚠️ these packages in the index depend on this version:"
+            )?;
+            for dep in &self.dependents {
+                writeln!(
+                    f,
+                    "{indent_spaces}* - {} {}",
+                    Id::from(dep.id.clone()),
+                    dep.version
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        // unwrap: all of our maps have string keys, so serialization can't fail.
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn failure_location(&self) -> Option<(&str, u64)> {
+        (!self.is_good()).then_some((self.index_path.as_str(), self.line))
+    }
 }
 
-/// A diagnostic for showing that an unexpected path was modified.
+/// A diagnostic for showing that an unexpected path was modified, or that a
+/// file was changed in a way that index files can't be.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename = "path")]
 struct PathReport {
+    #[serde(skip)]
     is_good: bool,
+    /// The path after the change (or before it, if the file was deleted).
     path: String,
+    #[serde(flatten)]
+    change: PathChange,
+    /// For changes to ownership files, whether the submitter can touch each
+    /// package whose ownership file changed. Empty if we didn't check.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<Permission>,
+}
+
+impl PathReport {
+    fn new(is_good: bool, path: String, change: PathChange) -> Self {
+        Self {
+            is_good,
+            path,
+            change,
+            permissions: Vec::new(),
+        }
+    }
+
+    /// The ownership files that this change touches.
+    fn owners_paths(&self) -> impl Iterator<Item = &str> {
+        let from = match &self.change {
+            PathChange::Renamed { from } => Some(from.as_str()),
+            _ => None,
+        };
+        std::iter::once(self.path.as_str())
+            .chain(from)
+            .filter(|p| is_owners_path(p))
+    }
+
+    /// Checks that the submitter could already publish every package whose
+    /// ownership file this change touches. Otherwise, they could give
+    /// themselves permission to publish someone else's package.
+    async fn check_owners(
+        &mut self,
+        submitter: Option<&Submitter<'_, impl Github>>,
+        index_dir: &Path,
+    ) -> miette::Result<()> {
+        let paths: Vec<_> = self.owners_paths().map(str::to_owned).collect();
+        for path in paths {
+            let Some((org, repo, index_path)) = owners::owned_package(&path) else {
+                self.is_good = false;
+                continue;
+            };
+            if let Some(perm) =
+                Permission::for_index_path(submitter, index_dir, org, repo, index_path).await?
+            {
+                self.is_good &= perm.is_allowed;
+                self.permissions.push(perm);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum PathChange {
+    Added,
+    Modified,
+    Renamed { from: String },
+    Deleted,
+    Binary,
+    NoLines,
+    NotAFile { file_type: String },
+    ModeChanged { old_mode: String, new_mode: String },
 }
 
 impl ReportItem for PathReport {
@@ -206,88 +916,402 @@ impl ReportItem for PathReport {
     fn format_with_indent(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
         let sym = if self.is_good { "⚠️" } else { "❌" };
         let path = &self.path;
-        writeln!(f, "{indent}{sym} this PR modifies {path}")
+        match &self.change {
+            PathChange::Added => writeln!(f, "{indent}{sym} this PR adds {path}"),
+            PathChange::Modified => writeln!(f, "{indent}{sym} this PR modifies {path}"),
+            PathChange::Renamed { from } => {
+                write!(f, "{indent}{sym} this PR renames {from} to {path}")?;
+                if is_index_path(from) || is_index_path(path) {
+                    write!(f, ": index files can't be renamed")?;
+                }
+                writeln!(f)
+            }
+            PathChange::Deleted => {
+                write!(f, "{indent}{sym} this PR deletes {path}")?;
+                if is_index_path(path) {
+                    write!(
+                        f,
+                        ": packages can't be removed from the index, but they can be yanked"
+                    )?;
+                }
+                writeln!(f)
+            }
+            PathChange::Binary => {
+                write!(
+                    f,
+                    "{indent}{sym} this PR gives {path} contents that aren't text"
+                )?;
+                if is_index_path(path) {
+                    write!(f, ": index files must be text")?;
+                }
+                writeln!(f)
+            }
+            PathChange::NoLines => writeln!(
+                f,
+                "{indent}{sym} this PR changes {path} without adding any lines to it"
+            ),
+            PathChange::NotAFile { file_type } => writeln!(
+                f,
+                "{indent}{sym} this PR makes {path} a {file_type}: only regular files are allowed"
+            ),
+            PathChange::ModeChanged { old_mode, new_mode } => writeln!(
+                f,
+                "{indent}{sym} this PR changes the mode of {path} from {old_mode} to {new_mode}"
+            ),
+        }?;
+
+        let indent_spaces = " ".repeat(indent.len());
+        for path in self.owners_paths() {
+            if owners::owned_package(path).is_none() {
+                writeln!(
+                    f,
+                    "{indent_spaces}*❌ {path} isn't where a package's ownership file goes"
+                )?;
+            }
+        }
+        if self.owners_paths().next().is_some() && self.permissions.is_empty() {
+            format_permission(f, &indent_spaces, None)?;
+        }
+        for perm in &self.permissions {
+            format_permission(f, &indent_spaces, Some(perm))?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        // unwrap: this is just strings, so serialization can't fail.
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn failure_location(&self) -> Option<(&str, u64)> {
+        // There's no particular line to point at, so point at the start of the
+        // file. A deleted file has nowhere to point at.
+        let exists = !matches!(self.change, PathChange::Deleted);
+        (!self.is_good && exists).then_some((self.path.as_str(), 1))
+    }
+}
+
+/// A change to an index file that we couldn't make sense of.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename = "invalid_change")]
+struct InvalidChangeReport {
+    #[serde(serialize_with = "json::display")]
+    error: package::Error,
+}
+
+impl ReportItem for InvalidChangeReport {
+    fn is_good(&self) -> bool {
+        false
+    }
+
+    fn format_with_indent(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        if !matches!(self.error, package::Error::Deserialize { .. }) {
+            return writeln!(f, "{indent}❌ {}", self.error);
+        }
+        // The rendered error includes a snippet of the index, which needs a
+        // code block to survive markdown.
+        let indent_spaces = " ".repeat(indent.len());
+        writeln!(f, "{indent}❌ invalid index entry:")?;
+        writeln!(f, "{indent_spaces}```")?;
+        for line in self.error.render().trim_end().lines() {
+            writeln!(f, "{indent_spaces}{line}")?;
+        }
+        writeln!(f, "{indent_spaces}```")
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        // unwrap: this is just a string, so serialization can't fail.
+        serde_json::to_value(self).unwrap()
+    }
+
+    fn failure_location(&self) -> Option<(&str, u64)> {
+        self.error.location()
     }
 }
 
+#[derive(Serialize)]
+#[serde(tag = "status", content = "details", rename_all = "snake_case")]
 enum PackageStatus {
     FetchFailed(String),
+    BadSubdir(SubdirError),
     EvalFailed(String),
     Manifest(Box<ManifestChecks>),
 }
 
-/// Checks the paths of modified files. Removes the ones that aren't modifying
-/// packages and adds diagnostic messages for them.
-fn check_diff_paths(patches: &mut Vec<Patch>, reports: &mut Vec<Box<dyn ReportItem>>) {
-    patches.retain(|patch| {
-        let path = &patch.new.path;
-        let mut parts = path.split('/');
-        if parts.next() != Some("b") {
-            reports.push(Box::new(PathReport {
-                is_good: false,
-                path: patch.new.path.clone().into_owned(),
-            }));
-            return false;
-        }
+/// Checks what a diff does to each file. Returns the patches that add to or
+/// modify index files, and adds diagnostic messages for everything else.
+///
+/// The messages for changes to ownership files still need their permissions
+/// checked, with [`PathReport::check_owners`].
+fn check_diff_paths<'a>(
+    changes: Vec<FileChange<'a>>,
+    reports: &mut Vec<PathReport>,
+) -> Vec<Patch<'a>> {
+    let mut patches = Vec::new();
+    for change in changes {
+        let (path, change) = match change {
+            FileChange::Added(patch) | FileChange::Modified(patch) => {
+                let added = patch.old.path == "/dev/null";
+                let Some(path) = patch.new.path.strip_prefix("b/") else {
+                    reports.push(PathReport::new(
+                        false,
+                        patch.new.path.clone().into_owned(),
+                        PathChange::Modified,
+                    ));
+                    continue;
+                };
+                if is_index_path(path) {
+                    // An empty file, or a binary one in a plain diff: there's
+                    // nothing for the index checks to look at, so it can't
+                    // be a valid change.
+                    if patch.hunks.is_empty() {
+                        reports.push(PathReport::new(false, path.to_owned(), PathChange::NoLines));
+                    } else {
+                        patches.push(patch);
+                    }
+                    continue;
+                }
+                let change = if added {
+                    PathChange::Added
+                } else {
+                    PathChange::Modified
+                };
+                (path.to_owned(), change)
+            }
+            // Index files only ever grow, so none of these make sense for
+            // them. Elsewhere, they're treated like modifications.
+            FileChange::Renamed { from, to } => {
+                let is_good = needs_review(&from) && needs_review(&to);
+                reports.push(PathReport::new(is_good, to, PathChange::Renamed { from }));
+                continue;
+            }
+            FileChange::Deleted { path } => (path, PathChange::Deleted),
+            FileChange::Binary { path } => (path, PathChange::Binary),
+            FileChange::NotAFile { path, kind } => {
+                reports.push(PathReport::new(
+                    false,
+                    path,
+                    PathChange::NotAFile { file_type: kind },
+                ));
+                continue;
+            }
+            FileChange::ModeChanged {
+                path,
+                old_mode,
+                new_mode,
+            } => (path, PathChange::ModeChanged { old_mode, new_mode }),
+        };
+        reports.push(PathReport::new(needs_review(&path), path, change));
+    }
+    patches
+}
+
+/// Is this path (without a "b/" prefix) an index file, or at least somewhere
+/// that index files go?
+fn is_index_path(path: &str) -> bool {
+    path.split('/').next() == Some("github")
+}
+
+/// Is this path (without a "b/" prefix) in the ownership registry?
+fn is_owners_path(path: &str) -> bool {
+    path.split('/').next() == Some(owners::OWNERS_DIR)
+}
 
-        // Trim off the "b/" for a better error message.
-        let path_without_prefix = &patch.new.path[2..];
-        let dir = parts.next();
-        if dir != Some("github") {
-            // Modifications to our CI are not necessarily bad. Any other path
-            // is definitely a mistake.
-            let is_good = dir == Some(".github");
-            reports.push(Box::new(PathReport {
-                is_good,
-                path: path_without_prefix.to_owned(),
-            }));
-            return false;
+/// Modifications to our CI and to the ownership registry are not necessarily
+/// bad, but they need a human to look at them. Changes to any other path outside
+/// the index are definitely a mistake.
+fn needs_review(path: &str) -> bool {
+    path.split('/').next() == Some(".github") || is_owners_path(path)
+}
+
+/// Checks a diff to the index.
+///
+/// `index` is the index before the diff, and `index_config` says where it lives.
+async fn make_report(
+    diff: &str,
+    submitter: Option<&Submitter<'_, impl Github>>,
+    index: &PackageIndex<Shared>,
+    index_config: &Config,
+    config: &CheckConfig,
+) -> miette::Result<Report> {
+    match diff::file_changes(diff) {
+        Ok(file_changes) => {
+            check_changes(file_changes, submitter, index, index_config, config).await
         }
-        true
-    });
+        Err(e) => Ok(Report::InvalidDiff(e.into())),
+    }
 }
 
-async fn make_report(diff: &str, client: &Octocrab, user: &str) -> miette::Result<Report> {
-    let mut reports = Vec::new();
-    let mut patches = match Patch::from_multiple(diff) {
-        Ok(p) => p,
-        Err(e) => return Ok(Report::InvalidDiff(e.into())),
-    };
-    check_diff_paths(&mut patches, &mut reports);
-    let pkgs = match package::changed_packages(patches) {
-        Ok(p) => p,
-        Err(e) => return Ok(Report::InvalidDiff(e)),
-    };
+/// Checks some changes to the index, however we got them.
+async fn check_changes(
+    file_changes: Vec<FileChange<'_>>,
+    submitter: Option<&Submitter<'_, impl Github>>,
+    index: &PackageIndex<Shared>,
+    index_config: &Config,
+    config: &CheckConfig,
+) -> miette::Result<Report> {
+    let mut path_reports = Vec::new();
+    let patches = check_diff_paths(file_changes, &mut path_reports);
+    let mut reports: Vec<Box<dyn ReportItem>> = Vec::new();
+    for mut report in path_reports {
+        report
+            .check_owners(submitter, &index_config.index_dir)
+            .await?;
+        reports.push(Box::new(report));
+    }
+    let (changes, errors) = package::changed_packages(patches);
+    for error in errors {
+        reports.push(Box::new(InvalidChangeReport { error }));
+    }
 
-    let index = PackageIndex::refreshed(Config::new().into_diag()?).into_diag()?;
-    for pkg in pkgs {
-        reports.push(Box::new(
-            PackageReport::new(client, user, &index, pkg).await?,
-        ));
+    // Start fetching and checking all the added packages, at most `config.jobs`
+    // at a time. We wait for them in order, so the report order doesn't depend
+    // on which ones finish first. If we bail out early, dropping the rest
+    // aborts them.
+    let jobs = Arc::new(Semaphore::new(config.jobs.get()));
+    let mut fetched: VecDeque<_> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Added(added) => Some(FetchedChecks::spawn(
+                added.pkg.clone(),
+                index_config.clone(),
+                config.clone(),
+                jobs.clone(),
+            )),
+            Change::Yanked(_) => None,
+        })
+        .collect();
+
+    // The versions submitted so far in this diff, for each package.
+    let mut submitted: HashMap<Id, Vec<SemVer>> = HashMap::new();
+    for change in changes {
+        match change {
+            Change::Added(pkg) => {
+                // unwrap: we spawned one task for each added package.
+                let checks = fetched.pop_front().unwrap().join().await??;
+                let earlier = submitted.entry(pkg.pkg.id.clone().into()).or_default();
+                let version = pkg.pkg.version.clone();
+                let report = PackageReport::new(
+                    submitter,
+                    index,
+                    index_config,
+                    pkg,
+                    earlier,
+                    config,
+                    checks,
+                )
+                .await?;
+                earlier.push(version);
+                reports.push(Box::new(report));
+            }
+            Change::Yanked(pkg) => {
+                let report = YankReport::new(submitter, index_config, pkg).await?;
+                reports.push(Box::new(report));
+            }
+        }
     }
 
     Ok(Report::PackageReports(reports))
 }
 
+/// Reads a diff from a file, or from stdin if the path is `-`.
+fn read_diff(path: &Path) -> miette::Result<String> {
+    if path == Path::new("-") {
+        let mut diff = String::new();
+        std::io::stdin()
+            .read_to_string(&mut diff)
+            .into_diagnostic()?;
+        Ok(diff)
+    } else {
+        std::fs::read_to_string(path).into_diagnostic()
+    }
+}
+
+/// Prints the report to stdout, or writes it to a file.
+fn print_report(report: &Report, format: Format, output: Option<&Path>) -> miette::Result<()> {
+    let rendered = report.render(format)?;
+    match output {
+        Some(path) => std::fs::write(path, rendered).into_diagnostic(),
+        None => {
+            println!("{rendered}");
+            Ok(())
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> miette::Result<()> {
     let args = Args::parse();
-    let mut builder = Octocrab::builder();
-
-    if let Some(tok) = args.token {
-        builder = builder.personal_token(tok);
+    if let Some(dir) = &args.eval_package {
+        eval::run_in_child(dir, args.eval_memory_limit);
+        return Ok(());
     }
-    let client = builder.build().into_diagnostic()?;
-    let pr_handler = client.pulls(&args.owner, &args.repo);
-    let diff = pr_handler.get_diff(args.pr).await.into_diagnostic()?;
-    let report = make_report(&diff, &client, &args.reporter).await?;
-    println!("{report}");
+    let config = args.check_config();
 
-    client
-        .issues(&args.owner, &args.repo)
-        .create_comment(args.pr, report.to_string())
-        .await
-        .into_diagnostic()?;
+    let index_config = args.index_config()?;
+    let index = PackageIndex::refreshed(index_config.clone()).into_diag()?;
+
+    let report = match (&args.diff_file, args.pr) {
+        (Some(path), _) => {
+            let diff = read_diff(path)?;
+            let submitter: Option<&Submitter<GithubClient>> = None;
+            let report = make_report(&diff, submitter, &index, &index_config, &config).await?;
+            print_report(&report, args.format, args.output.as_deref())?;
+            report
+        }
+        (None, Some(pr_args)) => {
+            let github = match (pr_args.app_id, &pr_args.app_private_key) {
+                (Some(id), Some(key)) => {
+                    GithubClient::with_app(id, key, &pr_args.owner, &pr_args.repo).await?
+                }
+                _ => GithubClient::with_token(pr_args.token)?,
+            };
+            let history = match &args.index_repo {
+                Some(path) => Some(IndexHistory::open(path, &args.index_base)?),
+                None => None,
+            };
+            let submitter = Submitter {
+                github: &github,
+                user: &pr_args.reporter,
+                index_owner: &pr_args.owner,
+                index_repo: &pr_args.repo,
+                history: history.as_ref(),
+            };
+            let report = match (&args.index_repo, &args.head) {
+                (Some(path), Some(head)) => {
+                    let tree_diff = TreeDiff::new(path, &args.index_base, head)?;
+                    let changes = tree_diff.file_changes();
+                    check_changes(changes, Some(&submitter), &index, &index_config, &config).await?
+                }
+                _ => {
+                    let diff = github
+                        .pr_diff(&pr_args.owner, &pr_args.repo, pr_args.pr)
+                        .await?;
+                    make_report(&diff, Some(&submitter), &index, &index_config, &config).await?
+                }
+            };
+            print_report(&report, args.format, args.output.as_deref())?;
+
+            if !pr_args.dry_run && pr_args.check_run {
+                check_run::publish(&github, &pr_args.owner, &pr_args.repo, pr_args.pr, &report)
+                    .await?;
+            }
+            if !pr_args.dry_run {
+                comment::post(
+                    &github,
+                    &pr_args.owner,
+                    &pr_args.repo,
+                    pr_args.pr,
+                    &report.to_string(),
+                    pr_args.keep_history,
+                )
+                .await?;
+            }
+            report
+        }
+        // clap ensures that exactly one of these is present.
+        (None, None) => unreachable!(),
+    };
 
     if report.is_good() {
         Ok(())
@@ -298,9 +1322,32 @@ async fn main() -> miette::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use gitpatch::Patch;
+    use std::{
+        num::NonZeroUsize,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc,
+        },
+    };
 
-    use crate::{Report, check_diff_paths};
+    use clap::{CommandFactory as _, Parser as _};
+    use nickel_lang_package::{
+        config::Config,
+        index::{Package, PackageIndex, serialize::PackageFormat},
+        version::SemVer,
+    };
+    use tokio::sync::Semaphore;
+
+    use crate::{
+        Args, Grant, Permission, Report, Submitter, check_diff_paths, diff,
+        github::FakeGithub,
+        history::IndexHistory,
+        make_report,
+        owners::Owners,
+        package::{self, CheckConfig},
+        spawn_job, test_repo,
+    };
 
     const SAMPLE_CI_DIFF: &str = r#"
 diff --git a/.github/workflows/foo.yaml b/.github/workflows/foo.yaml
@@ -325,12 +1372,11 @@ index df1cd2a..2229806 100644
     #[test]
     fn test_ci_changes() {
         let mut reports = Vec::new();
-        let mut patches = Patch::from_multiple(SAMPLE_CI_DIFF).unwrap();
-        check_diff_paths(&mut patches, &mut reports);
+        let patches = check_diff_paths(diff::file_changes(SAMPLE_CI_DIFF).unwrap(), &mut reports);
 
         // The CI patch should have been removed from the list.
         assert!(patches.is_empty());
-        let report = Report::PackageReports(reports);
+        let report = Report::from(reports);
         assert!(
             report
                 .to_string()
@@ -339,14 +1385,62 @@ index df1cd2a..2229806 100644
         assert!(report.is_good());
     }
 
+    #[tokio::test]
+    async fn test_owners_changes() {
+        let github = FakeGithub::default();
+        let owners_diff = |from: &str, to: &str| {
+            SAMPLE_CI_DIFF
+                .replace("a/.github/workflows/foo.yaml", &format!("a/{from}"))
+                .replace("b/.github/workflows/foo.yaml", &format!("b/{to}"))
+        };
+        let pkg_owners = owners_diff("owners/github/org/pkg", "owners/github/org/pkg");
+
+        // The package's owner can change who else owns it, but it still needs review.
+        let report = check_as(&github, Some("org"), &pkg_owners).await;
+        assert!(report.is_good());
+        let rendered = report.to_string();
+        assert!(rendered.contains("⚠️ this PR modifies owners/github/org/pkg"));
+        assert!(rendered.contains("✅ this PR is by org, the owner of org/pkg"));
+
+        // Someone else can't, or they could give themselves permission to publish it.
+        let report = check_as(&github, Some("eve"), &pkg_owners).await;
+        assert!(!report.is_good());
+        let rendered = report.to_string();
+        assert!(rendered.contains("❌ this PR modifies owners/github/org/pkg"));
+        assert!(rendered.contains("❌ this PR is by eve"));
+
+        // That goes for packages that aren't in the index yet, too.
+        let new_pkg = owners_diff("owners/github/victim/pkg", "owners/github/victim/pkg");
+        assert!(!check_as(&github, Some("eve"), &new_pkg).await.is_good());
+        assert!(check_as(&github, Some("victim"), &new_pkg).await.is_good());
+
+        // Renaming needs permission for both packages.
+        let renamed = owners_diff("owners/github/org/pkg", "owners/github/eve/pkg");
+        assert!(!check_as(&github, Some("eve"), &renamed).await.is_good());
+
+        // Files that aren't for any package have no business being there.
+        let stray = owners_diff("owners/README.md", "owners/README.md");
+        let report = check_as(&github, Some("org"), &stray).await;
+        assert!(!report.is_good());
+        assert!(
+            report
+                .to_string()
+                .contains("owners/README.md isn't where a package's ownership file goes")
+        );
+
+        // We can't check a local diff, so it only gets a warning.
+        let report = check_as(&github, None, &pkg_owners).await;
+        assert!(report.is_good());
+        assert!(report.to_string().contains("⚠️ didn't check permissions"));
+    }
+
     #[test]
     fn test_bad_path_changes() {
         let mut reports = Vec::new();
-        let mut patches = Patch::from_multiple(BAD_PATH_DIFF).unwrap();
-        check_diff_paths(&mut patches, &mut reports);
+        let patches = check_diff_paths(diff::file_changes(BAD_PATH_DIFF).unwrap(), &mut reports);
 
         assert!(patches.is_empty());
-        let report = Report::PackageReports(reports);
+        let report = Report::from(reports);
         assert!(
             report
                 .to_string()
@@ -354,4 +1448,441 @@ index df1cd2a..2229806 100644
         );
         assert!(!report.is_good());
     }
+
+    #[test]
+    fn test_renames_and_deletions() {
+        let changed = r#"diff --git a/github/org/pkg b/github/other/pkg
+similarity index 100%
+rename from github/org/pkg
+rename to github/other/pkg
+diff --git a/github/org/old b/github/org/old
+deleted file mode 100644
+index df1cd2a..0000000
+--- a/github/org/old
++++ /dev/null
+@@ -1 +0,0 @@
+-foo
+diff --git a/github/org/exe b/github/org/exe
+old mode 100644
+new mode 100755
+diff --git a/.github/old.yaml b/.github/new.yaml
+similarity index 100%
+rename from .github/old.yaml
+rename to .github/new.yaml
+diff --git a/github/org/empty b/github/org/empty
+new file mode 100644
+index 0000000..e69de29
+diff --git a/github/org/bin b/github/org/bin
+index 1111111..3333333 100644
+Binary files a/github/org/bin and b/github/org/bin differ
+"#;
+        let mut reports = Vec::new();
+        let patches = check_diff_paths(diff::file_changes(changed).unwrap(), &mut reports);
+        assert!(patches.is_empty());
+        let goods: Vec<_> = reports.iter().map(|r| r.is_good).collect();
+        assert_eq!(goods, [false, false, false, true, false, false]);
+
+        let report = Report::from(reports).to_string();
+        assert!(report.contains(
+            "❌ this PR renames github/org/pkg to github/other/pkg: index files can't be renamed"
+        ));
+        assert!(report.contains("❌ this PR deletes github/org/old: packages can't be removed"));
+        assert!(
+            report.contains("❌ this PR changes the mode of github/org/exe from 100644 to 100755")
+        );
+        assert!(report.contains("⚠️ this PR renames .github/old.yaml to .github/new.yaml\n"));
+        assert!(report.contains("❌ this PR changes github/org/empty without adding any lines"));
+        assert!(report.contains(
+            "❌ this PR gives github/org/bin contents that aren't text: index files must be text"
+        ));
+    }
+
+    #[test]
+    fn test_json_report() {
+        let mut reports = Vec::new();
+        check_diff_paths(diff::file_changes(BAD_PATH_DIFF).unwrap(), &mut reports);
+        let report = Report::from(reports);
+        let json = serde_json::to_value(report.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "schema_version": 1,
+                "good": false,
+                "invalid_diff": null,
+                "items": [{
+                    "kind": "path",
+                    "good": false,
+                    "path": "weird_path/foo.yaml",
+                    "change": "modified",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+
+        let pr_args = [
+            "customs",
+            "--owner",
+            "nickel-lang",
+            "--repo",
+            "nickel-mine",
+            "--reporter",
+            "someone",
+            "--pr",
+            "1",
+        ];
+        let args = Args::try_parse_from(pr_args).unwrap();
+        assert!(args.diff_file.is_none());
+        let pr = args.pr.unwrap();
+        assert_eq!(pr.pr, 1);
+        assert!(!pr.dry_run);
+
+        let args = Args::try_parse_from(pr_args.into_iter().chain(["--dry-run"])).unwrap();
+        assert!(args.pr.unwrap().dry_run);
+
+        let args = Args::try_parse_from(["customs", "--diff-file", "-"]).unwrap();
+        assert!(args.pr.is_none());
+        assert_eq!(args.latest_nickel_version, package::LATEST_NICKEL_VERSION);
+        let newer = [
+            "customs",
+            "--diff-file",
+            "-",
+            "--latest-nickel-version",
+            "2.0.0",
+        ];
+        let args = Args::try_parse_from(newer).unwrap();
+        assert_eq!(args.latest_nickel_version, SemVer::new(2, 0, 0));
+
+        // Reading a PR's changes from git refs needs the index repository.
+        let git = ["--index-repo", ".", "--head", "pr"];
+        let args = Args::try_parse_from(pr_args.into_iter().chain(git)).unwrap();
+        assert_eq!(args.head.as_deref(), Some("pr"));
+        assert!(Args::try_parse_from(pr_args.into_iter().chain(["--head", "pr"])).is_err());
+        let local = ["customs", "--diff-file", "-"].into_iter().chain(git);
+        assert!(Args::try_parse_from(local).is_err());
+
+        // Exactly one of the input modes must be given, and PR mode needs all its arguments.
+        assert!(Args::try_parse_from(["customs"]).is_err());
+        assert!(Args::try_parse_from(["customs", "--owner", "nickel-lang"]).is_err());
+        assert!(Args::try_parse_from(pr_args.into_iter().chain(["--diff-file", "-"])).is_err());
+
+        // App authentication needs both the ID and the key, and replaces the token.
+        let app = ["--app-id", "1", "--app-private-key", "key.pem"];
+        let args = Args::try_parse_from(pr_args.into_iter().chain(app)).unwrap();
+        assert_eq!(args.pr.unwrap().app_id, Some(1));
+        assert!(Args::try_parse_from(pr_args.into_iter().chain(["--app-id", "1"])).is_err());
+        let with_token = pr_args.into_iter().chain(app).chain(["--token", "t"]);
+        assert!(Args::try_parse_from(with_token).is_err());
+    }
+
+    /// An index entry for `github:org/pkg`.
+    const PKG_LINE: &str = r#"{"id":{"github":{"org":"org","name":"pkg","commit":"3ac728792d4a71f53897b185445b77029c3ce245"}},"version":{"major":1,"minor":0,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{},"authors":[],"description":"a package","keywords":[],"license":"MIT","v":0}"#;
+
+    /// Makes an index containing `github:org/pkg`, which is owned by `org/team`.
+    fn temp_index() -> (tempfile::TempDir, Config) {
+        let cache = tempfile::tempdir().unwrap();
+        let index_config = Config::new()
+            .unwrap()
+            .with_cache_dir(cache.path().to_owned());
+        let pkg: PackageFormat = serde_json::from_str(PKG_LINE).unwrap();
+        PackageIndex::exclusive(index_config.clone())
+            .unwrap()
+            .save(Package::from(pkg))
+            .unwrap();
+        let owners = index_config.index_dir.join("owners/github/org");
+        std::fs::create_dir_all(&owners).unwrap();
+        std::fs::write(owners.join("pkg"), r#"{"teams": ["org/team"]}"#).unwrap();
+        (cache, index_config)
+    }
+
+    /// Checks `diff` against the index from [`temp_index`], as if it was a PR
+    /// by `user`, or a local diff if `user` is `None`.
+    async fn check_as(github: &FakeGithub, user: Option<&str>, diff: &str) -> Report {
+        let (_cache, index_config) = temp_index();
+        let index = PackageIndex::shared(index_config.clone()).unwrap();
+        let submitter = user.map(|user| Submitter {
+            github,
+            user,
+            index_owner: "nickel-lang",
+            index_repo: "nickel-mine",
+            history: None,
+        });
+        make_report(
+            diff,
+            submitter.as_ref(),
+            &index,
+            &index_config,
+            &CheckConfig::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Checks a PR by `user` that yanks `github:org/pkg`. Yanks don't need to
+    /// fetch anything, so this runs the whole pipeline without the network.
+    async fn check_yank(github: &FakeGithub, user: &str) -> Report {
+        let yanked = PKG_LINE.replace(r#""v":0}"#, r#""v":0,"yanked":true}"#);
+        let diff = format!(
+            "diff --git a/github/org/pkg b/github/org/pkg\n\
+             index df1cd2a..2229806 100644\n\
+             --- a/github/org/pkg\n\
+             +++ b/github/org/pkg\n\
+             @@ -1 +1 @@\n\
+             -{PKG_LINE}\n\
+             +{yanked}\n"
+        );
+        check_as(github, Some(user), &diff).await
+    }
+
+    #[tokio::test]
+    async fn test_yanks() {
+        // The owner can yank their package.
+        let report = check_yank(&FakeGithub::default(), "org").await;
+        assert!(report.is_good());
+        let rendered = report.to_string();
+        assert!(rendered.contains("✅ this PR is by org"));
+        assert!(rendered.contains("yanking package org/pkg"));
+
+        // Someone else can't.
+        let report = check_yank(&FakeGithub::default(), "eve").await;
+        assert!(!report.is_good());
+        assert!(report.to_string().contains("❌ this PR is by eve"));
+    }
+
+    #[tokio::test]
+    async fn test_permissions() {
+        let pair = |a: &str, b: &str| (a.to_owned(), b.to_owned());
+        let mut github = FakeGithub {
+            public_members: [pair("org", "alice")].into(),
+            private_members: [pair("org", "bob")].into(),
+            collaborators: [pair("org/pkg", "carol")].into(),
+            team_members: [pair("org/team", "dave")].into(),
+            ..Default::default()
+        };
+
+        let allowed = [
+            ("org", "the owner of org/pkg"),
+            ("Org", "the owner of org/pkg"),
+            ("alice", "a member of org"),
+            ("dave", "a member of org/team"),
+        ];
+        for (user, reason) in allowed {
+            let report = check_yank(&github, user).await;
+            assert!(report.is_good(), "{user}");
+            assert!(report.to_string().contains(reason), "{user}");
+        }
+        // We can't see private members or collaborators without our app.
+        for user in ["bob", "carol", "eve"] {
+            assert!(!check_yank(&github, user).await.is_good(), "{user}");
+        }
+
+        github.installed.insert("org/pkg".to_owned());
+        assert!(check_yank(&github, "bob").await.is_good());
+        let report = check_yank(&github, "carol").await;
+        assert!(report.to_string().contains("a collaborator on org/pkg"));
+        assert!(!check_yank(&github, "eve").await.is_good());
+    }
+
+    #[tokio::test]
+    async fn test_previous_publisher() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(dir.path());
+        let path = "github/org/pkg";
+        // Ivan's PR, with a commit that claims to be by frank.
+        let first = test_repo::commit(&repo, "1+frank@users.noreply.github.com", path, "v1\n");
+        let second = test_repo::commit(&repo, "grace@example.com", path, "v1\nv2\n");
+        // Someone else's email address doesn't make them the publisher.
+        test_repo::commit(&repo, "heidi@example.com", path, "v1\nv2\nv3\n");
+        let history = IndexHistory::open(dir.path(), "HEAD").unwrap();
+
+        let github = FakeGithub {
+            pr_authors: [
+                (first.to_string(), vec!["ivan".to_owned()]),
+                (second.to_string(), vec!["Grace".to_owned()]),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let check = async |user| {
+            let submitter = Submitter {
+                github: &github,
+                user,
+                index_owner: "nickel-lang",
+                index_repo: "nickel-mine",
+                history: Some(&history),
+            };
+            let owners = Owners::default();
+            Permission::check(&submitter, "org".into(), "pkg".into(), &owners, path)
+                .await
+                .unwrap()
+        };
+        for user in ["ivan", "grace"] {
+            let perm = check(user).await;
+            assert!(
+                matches!(perm.granted_by, Some(Grant::PreviousPublisher)),
+                "{user}"
+            );
+        }
+        for user in ["frank", "heidi"] {
+            assert!(!check(user).await.is_allowed, "{user}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let jobs = Arc::new(Semaphore::new(2));
+
+        // The first job only finishes once the last one has, so it holds on
+        // to its permit while all the others run.
+        let (done, wait) = mpsc::channel();
+        let (mut done, mut wait) = (Some(done), Some(wait));
+        let n = 5;
+        let tasks: Vec<_> = (0..n)
+            .map(|i| {
+                let wait = if i == 0 { wait.take() } else { None };
+                let done = if i == n - 1 { done.take() } else { None };
+                let (running, max_running) = (running.clone(), max_running.clone());
+                spawn_job(jobs.clone(), move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    if let Some(wait) = wait {
+                        wait.recv().unwrap();
+                    }
+                    if let Some(done) = done {
+                        done.send(()).unwrap();
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+
+        // The results come back in order, even though they finished out of order.
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.join().await.unwrap());
+        }
+        assert_eq!(results, [0, 1, 2, 3, 4]);
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_jobs_are_aborted() {
+        let jobs = Arc::new(Semaphore::new(1));
+        let (started, is_started) = tokio::sync::oneshot::channel();
+        let (release, wait) = mpsc::channel::<()>();
+        let first = spawn_job(jobs.clone(), move || {
+            started.send(()).unwrap();
+            // This fails once the test has stopped waiting for us.
+            let _ = wait.recv();
+        });
+        let ran = Arc::new(AtomicBool::new(false));
+        let second = spawn_job(jobs, {
+            let ran = ran.clone();
+            move || ran.store(true, Ordering::SeqCst)
+        });
+        is_started.await.unwrap();
+
+        // Stop waiting for them, like an early return would.
+        let second_handle = second.0.abort_handle();
+        drop((first, second));
+        release.send(()).unwrap();
+        while !second_handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_report_order() {
+        // `slow` can be fetched from the mirror, but `fast` fails immediately.
+        let mirrors = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(&mirrors.path().join("org/slow"));
+        let commit = test_repo::commit(&repo, "a@example.com", "README", "hi");
+        let slow = PKG_LINE
+            .replace(r#""name":"pkg""#, r#""name":"slow""#)
+            .replace(
+                "3ac728792d4a71f53897b185445b77029c3ce245",
+                &commit.to_string(),
+            );
+        let fast = PKG_LINE.replace(r#""name":"pkg""#, r#""name":"fast""#);
+        let new_file = |name: &str, line: &str| {
+            format!(
+                "diff --git a/github/org/{name} b/github/org/{name}\n\
+                 new file mode 100644\n\
+                 index 0000000..17e1150\n\
+                 --- /dev/null\n\
+                 +++ b/github/org/{name}\n\
+                 @@ -0,0 +1 @@\n\
+                 +{line}\n"
+            )
+        };
+        let diff = new_file("slow", &slow) + &new_file("fast", &fast);
+
+        let (_cache, index_config) = temp_index();
+        let mirror = format!("file://{}", mirrors.path().display());
+        let index_config = package::with_mirror(index_config, &mirror).unwrap();
+        let index = PackageIndex::shared(index_config.clone()).unwrap();
+        let config = CheckConfig {
+            jobs: NonZeroUsize::new(2).unwrap(),
+            ..CheckConfig::default()
+        };
+        let submitter: Option<&Submitter<FakeGithub>> = None;
+        let report = make_report(&diff, submitter, &index, &index_config, &config)
+            .await
+            .unwrap()
+            .to_string();
+
+        // The packages are reported in the order of the diff, each with the
+        // outcome of its own fetch.
+        let slow = report.find("package org/slow").unwrap();
+        let fast = report.find("package org/fast").unwrap();
+        assert!(slow < fast);
+        assert!(report[slow..fast].contains("✅ fetched package"));
+        assert!(report[fast..].contains("❌ failed to fetch package"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_changes() {
+        let yanked = PKG_LINE.replace(r#""v":0}"#, r#""v":0,"yanked":true}"#);
+        let bad = PKG_LINE.replace(r#""v":0"#, r#""v":}"#);
+        // One bad line doesn't stop us from checking the yank after it.
+        let diff = format!(
+            "diff --git a/github/org/pkg b/github/org/pkg\n\
+             index df1cd2a..2229806 100644\n\
+             --- a/github/org/pkg\n\
+             +++ b/github/org/pkg\n\
+             @@ -1 +1,2 @@\n\
+             -{PKG_LINE}\n\
+             +{bad}\n\
+             +{yanked}\n"
+        );
+        let (_cache, index_config) = temp_index();
+        let index = PackageIndex::shared(index_config.clone()).unwrap();
+        let submitter: Option<&Submitter<FakeGithub>> = None;
+        let report = make_report(
+            &diff,
+            submitter,
+            &index,
+            &index_config,
+            &CheckConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!report.is_good());
+        let Report::PackageReports(items) = &report else {
+            panic!("expected package reports");
+        };
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].failure_location(), Some(("github/org/pkg", 1)));
+        let rendered = report.to_string();
+        assert!(rendered.contains("[github/org/pkg:1:"));
+        assert!(rendered.contains("yanking package org/pkg"));
+    }
 }
@@ -0,0 +1,138 @@
+//! The ownership registry.
+//!
+//! By default, we only let the owner of a github org (or a member of it)
+//! submit packages from that org. Packages can widen that by having an
+//! ownership file in the index repository at `owners/<package path>`, where
+//! the package path is the same as the path of the package's index file. For
+//! example, the owners of `github/nickel-lang/json-schema-to-nickel%@lib`
+//! are listed in `owners/github/nickel-lang/json-schema-to-nickel%@lib`.
+//!
+//! An ownership file is a JSON object of the form
+//!
+//! ```json
+//! {
+//!   "users": ["some-github-user"],
+//!   "teams": ["some-org/some-team"]
+//! }
+//! ```
+//!
+//! where both fields are optional. Teams are given as `<org>/<team slug>`.
+//!
+//! We always read ownership files from the index as it was before the PR, so
+//! a PR can't give its own author permission. PRs that modify ownership files
+//! need a human to look at them, and are only accepted from someone who could
+//! already publish the package.
+
+use std::path::Path;
+
+use miette::IntoDiagnostic as _;
+use serde::Deserialize;
+
+use crate::github::Github;
+
+/// The directory of the index repository that ownership files live in.
+pub const OWNERS_DIR: &str = "owners";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Owners {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub teams: Vec<String>,
+}
+
+impl Owners {
+    /// Reads the ownership file of a package from the index, if there is one.
+    ///
+    /// `index_path` is the path of the package's index file, relative to the
+    /// root of the index.
+    pub fn load(index_dir: &Path, index_path: &Path) -> miette::Result<Option<Self>> {
+        let path = index_dir.join(OWNERS_DIR).join(index_path);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).into_diagnostic()?;
+        let owners = serde_json::from_str(&contents)
+            .into_diagnostic()
+            .map_err(|e| e.wrap_err(format!("invalid ownership file {}", path.display())))?;
+        Ok(Some(owners))
+    }
+
+    /// Is `user` one of the listed users? Github usernames are case-insensitive.
+    pub fn has_user(&self, user: &str) -> bool {
+        self.users.iter().any(|u| u.eq_ignore_ascii_case(user))
+    }
+}
+
+/// Finds the package that an ownership file is for.
+///
+/// `path` is relative to the root of the index. Returns the org and repository
+/// of the package, and the path of its index file, or `None` if `path` isn't
+/// where a package's ownership file would be.
+pub fn owned_package(path: &str) -> Option<(&str, &str, &str)> {
+    let index_path = path.strip_prefix(OWNERS_DIR)?.strip_prefix('/')?;
+    let parts: Vec<_> = index_path.split('/').collect();
+    let ["github", org, file] = parts.as_slice() else {
+        return None;
+    };
+    // Packages in subdirectories have the subdirectory appended to their
+    // file name after a `%`, which can't appear in repository names.
+    let repo = file.split_once('%').map_or(*file, |(repo, _)| repo);
+    let is_name = |s: &str| !s.is_empty() && s != "." && s != "..";
+    (is_name(org) && is_name(repo)).then_some((org, repo, index_path))
+}
+
+/// Is `user` an active member of `team` (given as `<org>/<team slug>`)?
+pub async fn is_team_member(github: &impl Github, team: &str, user: &str) -> miette::Result<bool> {
+    let Some((org, slug)) = team.split_once('/') else {
+        miette::bail!("invalid team `{team}`: expected `<org>/<team slug>`");
+    };
+    github.is_team_member(org, slug, user).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let index = tempfile::tempdir().unwrap();
+        let index_path = Path::new("github/org/pkg");
+        assert!(Owners::load(index.path(), index_path).unwrap().is_none());
+
+        let dir = index.path().join(OWNERS_DIR).join("github/org");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("pkg"), r#"{"users": ["Alice"]}"#).unwrap();
+        let owners = Owners::load(index.path(), index_path).unwrap().unwrap();
+        assert!(owners.has_user("alice"));
+        assert!(!owners.has_user("bob"));
+        assert!(owners.teams.is_empty());
+
+        std::fs::write(dir.join("pkg"), r#"{"user": ["alice"]}"#).unwrap();
+        assert!(Owners::load(index.path(), index_path).is_err());
+    }
+
+    #[test]
+    fn test_owned_package() {
+        assert_eq!(
+            owned_package("owners/github/org/pkg"),
+            Some(("org", "pkg", "github/org/pkg"))
+        );
+        assert_eq!(
+            owned_package("owners/github/org/pkg%@lib"),
+            Some(("org", "pkg", "github/org/pkg%@lib"))
+        );
+        for path in [
+            "owners/README.md",
+            "owners/github/org",
+            "owners/github/org/pkg/extra",
+            "owners/gitlab/org/pkg",
+            "owners/github/../pkg",
+            "ownersgithub/org/pkg",
+            "github/org/pkg",
+        ] {
+            assert_eq!(owned_package(path), None, "{path}");
+        }
+    }
+}
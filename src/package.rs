@@ -1,17 +1,35 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+};
 
 use gitpatch::Patch;
-use miette::{IntoDiagnostic as _, bail};
-use nickel_lang_core::error::report::{ColorOpt, report_as_str};
-use nickel_lang_git::{Spec, Target};
+use miette::{
+    Diagnostic, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic as _, MietteError,
+    MietteSpanContents, SourceCode, SourceSpan, SpanContents, bail,
+};
+use nickel_lang_core::{
+    error::report::{ColorOpt, report_as_str},
+    package::PackageMap,
+};
 use nickel_lang_package::{
-    IndexDependency, ManifestFile,
-    index::{Id, Package, PackageIndex, PreciseId, Shared, serialize::PackageFormat},
+    Dependency, IndexDependency, ManifestFile,
+    config::Config,
+    index::{Id, Package, PackageIndex, Shared, serialize::PackageFormat},
     manifest::MANIFEST_NAME,
+    resolve::{self, Resolution},
+    snapshot::Snapshot,
     version::SemVer,
 };
+use serde::Serialize;
 
-#[derive(Debug, thiserror::Error)]
+use crate::{
+    eval::{self, EvalCheck, EvalLimits, ImportError},
+    license::{LicenseChecks, LicensePolicy},
+};
+
+#[derive(Debug, thiserror::Error, Diagnostic)]
 pub enum Error {
     #[error("failed to parse diff: {0}")]
     Patch(String),
@@ -21,14 +39,110 @@ pub enum Error {
     MissingOrg(String),
     #[error("missing repo, got \"{0}\"")]
     MissingRepo(String),
-    #[error("you can't delete a line: \"{0}\"")]
-    Deletion(String),
-    #[error("invalid package spec: {0}")]
-    Deserialize(#[from] serde_json::Error),
+    #[error("you can't delete or modify a line of {path}, except to yank it: \"{text}\"")]
+    Deletion {
+        path: String,
+        /// Where in the new index file the line was removed.
+        line: u64,
+        text: String,
+    },
+    #[error("invalid package spec in {}, line {}: {reason}", src.path, src.line)]
+    Deserialize {
+        reason: String,
+        #[source_code]
+        src: IndexLine,
+        #[label("here")]
+        span: SourceSpan,
+    },
     #[error("org/name mismatch: path was \"{path}\", package was \"{package}\"")]
-    OrgNameMismatch { path: String, package: String },
+    OrgNameMismatch {
+        path: String,
+        line: u64,
+        package: String,
+    },
     #[error("path too deep: expected three components, got \"{path}\"")]
     PathToDeep { path: String },
+    #[error("line {line} of {path} must end with a single newline (\\n)")]
+    LineEnding { path: String, line: u64 },
+}
+
+impl Error {
+    fn deserialize(path: &str, line: u64, text: &str, e: serde_json::Error) -> Self {
+        // serde_json's columns are 1-based byte offsets, and they can point one
+        // past the end of the line if it ended too early.
+        let mut offset = e.column().saturating_sub(1).min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let len = text[offset..].chars().next().map_or(0, char::len_utf8);
+        // The error message ends with the position, which we report ourselves.
+        let reason = e.to_string();
+        let reason = reason.rsplit_once(" at line ").map_or(&*reason, |(r, _)| r);
+        Error::Deserialize {
+            reason: reason.to_owned(),
+            src: IndexLine {
+                path: path.to_owned(),
+                line,
+                text: text.to_owned(),
+            },
+            span: (offset, len).into(),
+        }
+    }
+
+    /// The index file and line that this error is about, if we know them.
+    pub fn location(&self) -> Option<(&str, u64)> {
+        match self {
+            Error::Deserialize { src, .. } => Some((&src.path, src.line)),
+            Error::Deletion { path, line, .. }
+            | Error::OrgNameMismatch { path, line, .. }
+            | Error::LineEnding { path, line } => Some((path, *line)),
+            _ => None,
+        }
+    }
+
+    /// Renders the error for humans, including a snippet of the offending
+    /// index line if there is one.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // unwrap: writing to a string can't fail.
+        GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+            .render_report(&mut out, self)
+            .unwrap();
+        out
+    }
+}
+
+/// A line of an index file, as source code for diagnostics.
+///
+/// We only keep the offending line around, but diagnostics should show its
+/// real line number in the index file.
+#[derive(Debug)]
+pub struct IndexLine {
+    path: String,
+    /// The (1-based) line number of `text` in the new index file.
+    line: u64,
+    text: String,
+}
+
+impl SourceCode for IndexLine {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self
+            .text
+            .read_span(span, context_lines_before, context_lines_after)?;
+        Ok(Box::new(MietteSpanContents::new_named(
+            self.path.clone(),
+            contents.data(),
+            *contents.span(),
+            contents.line() + self.line.saturating_sub(1) as usize,
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
 }
 
 impl<'a> From<gitpatch::ParseError<'a>> for Error {
@@ -37,98 +151,563 @@ impl<'a> From<gitpatch::ParseError<'a>> for Error {
     }
 }
 
-pub fn changed_packages(patches: Vec<Patch>) -> Result<Vec<Package>, Error> {
-    let mut ret = Vec::new();
-    for patch in patches {
-        let path = patch.new.path;
-        let mut parts = path.split('/');
-        if parts.next() != Some("b") {
-            return Err(Error::BadPrefix(path.into_owned()));
+/// A package that was added to the index, along with the place it was added.
+pub struct AddedPackage {
+    pub pkg: Package,
+    /// The path of the index file, relative to the root of the index.
+    pub path: String,
+    /// The (1-based) line number of the package's entry in the new index file.
+    pub line: u64,
+    pub format_check: FormatCheck,
+}
+
+/// Serializes a package the same way the package manager does when it writes
+/// the index.
+pub fn canonical_line(pkg: &Package) -> String {
+    // unwrap: all of our maps have string keys, so serialization can't fail.
+    serde_json::to_string(&PackageFormat::from(pkg.clone())).unwrap()
+}
+
+/// Is an added index entry written exactly the way the package manager would
+/// have written it?
+///
+/// Deserialization ignores unknown fields, key order and whitespace, so
+/// without this check hand-edited entries would make the index inconsistent.
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", content = "expected", rename_all = "snake_case")]
+pub enum FormatCheck {
+    Canonical,
+    /// Contains the canonical form of the entry.
+    NotCanonical(String),
+}
+
+impl FormatCheck {
+    pub fn new(line: &str, pkg: &Package) -> Self {
+        let canonical = canonical_line(pkg);
+        if line == canonical {
+            FormatCheck::Canonical
+        } else {
+            FormatCheck::NotCanonical(canonical)
         }
-        if parts.next() != Some("github") {
-            return Err(Error::BadPrefix(path.into_owned()));
+    }
+
+    pub fn is_good(&self) -> bool {
+        matches!(self, FormatCheck::Canonical)
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        match self {
+            FormatCheck::Canonical => {
+                writeln!(f, "{indent}✅ index entry is canonically formatted")
+            }
+            FormatCheck::NotCanonical(expected) => {
+                // Continuation lines of the list item need to line up with its text.
+                let cont = " ".repeat(indent.len() + 1);
+                writeln!(
+                    f,
+                    "{indent}❌ index entry isn't canonically formatted; it should be"
+                )?;
+                writeln!(f, "{cont}```json\n{cont}{expected}\n{cont}```")
+            }
         }
-        let Some(path_org) = parts.next() else {
-            return Err(Error::MissingOrg(path.into_owned()));
-        };
-        let Some(path_name) = parts.next() else {
-            return Err(Error::MissingRepo(path.into_owned()));
-        };
-        if parts.next().is_some() {
-            return Err(Error::PathToDeep {
-                path: path.into_owned(),
+    }
+}
+
+/// The field that a yank adds to the end of an index entry.
+const YANK_MARKER: &str = r#","yanked":true"#;
+
+/// A package that was yanked, along with the place it was yanked.
+///
+/// A yank is a modification of a canonically formatted index entry that only
+/// appends [`YANK_MARKER`] to it.
+pub struct YankedPackage {
+    pub pkg: Package,
+    /// The path of the index file, relative to the root of the index.
+    pub path: String,
+    /// The (1-based) line number of the package's entry in the new index file.
+    pub line: u64,
+}
+
+/// A change to the index.
+pub enum Change {
+    Added(AddedPackage),
+    Yanked(YankedPackage),
+}
+
+/// Is `new` exactly the same index entry as `old`, but yanked?
+fn is_yank(old: &str, new: &str) -> bool {
+    let Ok(pkg) = serde_json::from_str::<PackageFormat>(old) else {
+        return false;
+    };
+    let canonical = canonical_line(&Package::from(pkg));
+    let Some(fields) = canonical.strip_suffix('}') else {
+        return false;
+    };
+    old == canonical && new == format!("{fields}{YANK_MARKER}}}")
+}
+
+/// Finds the index changes made by some patches.
+///
+/// We don't stop at the first problem: the returned errors describe every
+/// change that we couldn't make sense of, and the well-formed changes are
+/// returned alongside them so that they can still be checked.
+pub fn changed_packages(patches: Vec<Patch>) -> (Vec<Change>, Vec<Error>) {
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+    for patch in patches {
+        patch_changes(patch, &mut changes, &mut errors);
+    }
+    (changes, errors)
+}
+
+/// Returns the index file path (without the "b/" prefix) that a patch modifies.
+fn index_path(patch: &Patch) -> Result<String, Error> {
+    let path = &patch.new.path;
+    let mut parts = path.split('/');
+    if parts.next() != Some("b") {
+        return Err(Error::BadPrefix(path.to_string()));
+    }
+    if parts.next() != Some("github") {
+        return Err(Error::BadPrefix(path.to_string()));
+    }
+    let Some(path_org) = parts.next() else {
+        return Err(Error::MissingOrg(path.to_string()));
+    };
+    let Some(path_name) = parts.next() else {
+        return Err(Error::MissingRepo(path.to_string()));
+    };
+    if parts.next().is_some() {
+        return Err(Error::PathToDeep {
+            path: path.to_string(),
+        });
+    }
+    Ok(format!("github/{path_org}/{path_name}"))
+}
+
+fn patch_changes(patch: Patch, changes: &mut Vec<Change>, errors: &mut Vec<Error>) {
+    let package_path = match index_path(&patch) {
+        Ok(path) => path,
+        Err(e) => {
+            errors.push(e);
+            return;
+        }
+    };
+    let parse = |line: &str, line_number: u64| -> Result<Package, Error> {
+        let package: PackageFormat = serde_json::from_str(line)
+            .map_err(|e| Error::deserialize(&package_path, line_number, line, e))?;
+        let package = Package::from(package);
+        let id = Id::from(package.id.clone());
+        if id.path().to_str() != Some(package_path.as_ref()) {
+            return Err(Error::OrgNameMismatch {
+                path: package_path.clone(),
+                line: line_number,
+                package: id.path().display().to_string(),
             });
         }
+        Ok(package)
+    };
 
-        for line in patch.hunks.iter().flat_map(|h| h.lines.iter()) {
+    for hunk in &patch.hunks {
+        let mut line_number = hunk.new_range.start;
+        // A removed line is reported where it was removed, but that can be
+        // just past the end of the hunk (or the file) if there's nothing after it.
+        let last_line = (hunk.new_range.start + hunk.new_range.count)
+            .saturating_sub(1)
+            .max(1);
+        let deletion = |(text, line): (&str, u64)| Error::Deletion {
+            path: package_path.clone(),
+            line: line.clamp(1, last_line),
+            text: text.to_owned(),
+        };
+        // Lines that were removed since the last context line, along with
+        // where they were removed. Each of these must be matched by an
+        // added line that yanks it.
+        let mut removed: Vec<(&str, u64)> = Vec::new();
+        for line in &hunk.lines {
             match line {
+                gitpatch::Line::Add(line) if line.ends_with('\r') => {
+                    errors.push(Error::LineEnding {
+                        path: package_path.clone(),
+                        line: line_number,
+                    });
+                    line_number += 1;
+                }
                 gitpatch::Line::Add(line) => {
-                    let package: PackageFormat = serde_json::from_str(line)?;
-                    let package = Package::from(package);
-                    let id = Id::from(package.id.clone());
-                    let package_path = format!("github/{path_org}/{path_name}");
-                    if id.path().to_str() != Some(package_path.as_ref()) {
-                        return Err(Error::OrgNameMismatch {
-                            path: package_path,
-                            package: id.path().display().to_string(),
-                        });
+                    let yanked = removed.iter().position(|(old, _)| is_yank(old, line));
+                    let change = if let Some(idx) = yanked {
+                        removed.remove(idx);
+                        parse(line, line_number).map(|pkg| {
+                            Change::Yanked(YankedPackage {
+                                pkg,
+                                path: package_path.clone(),
+                                line: line_number,
+                            })
+                        })
+                    } else {
+                        parse(line, line_number).map(|pkg| {
+                            Change::Added(AddedPackage {
+                                format_check: FormatCheck::new(line, &pkg),
+                                pkg,
+                                path: package_path.clone(),
+                                line: line_number,
+                            })
+                        })
+                    };
+                    match change {
+                        Ok(change) => changes.push(change),
+                        Err(e) => errors.push(e),
                     }
-                    ret.push(package);
+                    line_number += 1;
                 }
                 gitpatch::Line::Remove(line) => {
-                    return Err(Error::Deletion((*line).to_owned()));
+                    removed.push((line, line_number));
+                }
+                gitpatch::Line::Context(_) => {
+                    errors.extend(removed.drain(..).map(deletion));
+                    line_number += 1;
+                }
+            }
+        }
+        errors.extend(removed.into_iter().map(deletion));
+    }
+
+    // Without a final newline, the next entry to be added would end up on
+    // the same line as the last one.
+    if let (false, Some(hunk)) = (patch.end_newline, patch.hunks.last()) {
+        errors.push(Error::LineEnding {
+            path: package_path.clone(),
+            line: hunk.new_range.start + hunk.new_range.count.saturating_sub(1),
+        });
+    }
+}
+
+/// Finds all the packages in the index that depend on `id` with a version
+/// requirement that `version` satisfies.
+///
+/// These are the packages that are affected if `version` gets yanked.
+pub fn dependents(index_dir: &Path, id: &Id, version: &SemVer) -> miette::Result<Vec<Package>> {
+    let mut ret = Vec::new();
+    let mut dirs = vec![index_dir.join("github")];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            if entry.file_type().into_diagnostic()?.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(entry.path()).into_diagnostic()?;
+            for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+                let pkg =
+                    Package::from(serde_json::from_str::<PackageFormat>(line).into_diagnostic()?);
+                if pkg
+                    .dependencies
+                    .values()
+                    .any(|dep| &dep.id == id && dep.version.matches(version))
+                {
+                    ret.push(pkg);
                 }
-                gitpatch::Line::Context(_) => {}
             }
         }
     }
+    ret.sort_by(|a, b| (&a.id, &a.version).cmp(&(&b.id, &b.version)));
     Ok(ret)
 }
 
-/// Fetches a package.
+/// The github repository that the index lives in.
+const INDEX_REPO: &str = "nickel-lang/nickel-mine";
+
+/// Configures the package manager to fetch everything that it would fetch
+/// from github from `mirror` instead: the index from
+/// `<mirror>/nickel-lang/nickel-mine`, and packages from `<mirror>/<org>/<name>`.
+pub fn with_mirror(config: Config, mirror: &str) -> miette::Result<Config> {
+    let mirror: gix::Url = mirror.trim_end_matches('/').try_into().into_diagnostic()?;
+    let mut index_url = mirror.clone();
+    index_url
+        .path
+        .extend_from_slice(format!("/{INDEX_REPO}").as_bytes());
+    Ok(config
+        .with_index_url(index_url)
+        .with_github_package_url(mirror))
+}
+
+/// Points a manifest's git dependencies on github at the place that `config`
+/// fetches github packages from, so that they also come from the mirror if
+/// there is one.
+///
+/// Only direct dependencies are covered: we can't know about the git
+/// dependencies of git dependencies before fetching them.
+fn with_git_replacements(config: &Config, manifest: &ManifestFile) -> Config {
+    let mut config = config.clone();
+    if config.github_package_url.host() == Some("github.com") {
+        return config;
+    }
+    for dep in manifest.dependencies.values() {
+        let Dependency::Git(git) = dep else {
+            continue;
+        };
+        if git.url.host() != Some("github.com") {
+            continue;
+        }
+        let path = git.url.path.to_string();
+        let path = path.trim_start_matches('/').trim_end_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let mut url = config.github_package_url.clone();
+        url.path.extend_from_slice(format!("/{path}").as_bytes());
+        config.git_replacements.insert(git.url.clone(), url);
+    }
+    config
+}
+
+/// Fetches a package, from wherever `config` says that github packages come
+/// from (see [`with_mirror`]).
 ///
 /// This uses `nickel_lang_git`, with essentially the same code as nickel's package manager.
 /// In particular, this should catch any portability issues like illegal windows filenames.
-pub fn fetch(pkg: &Package, path: &Path) -> miette::Result<()> {
-    let PreciseId::Github {
-        org,
-        name,
-        commit,
-        path: _,
-    } = &pkg.id;
-    let url = format!("https://github.com/{org}/{name}.git");
-    let spec = Spec {
-        url: url.try_into().into_diagnostic()?,
-        target: Target::Commit(*commit),
-    };
-    nickel_lang_git::fetch(&spec, path).into_diagnostic()?;
+pub fn fetch(pkg: &Package, path: &Path, config: &Config) -> miette::Result<()> {
+    nickel_lang_git::fetch(&pkg.id.download_spec(config), path).into_diagnostic()?;
     Ok(())
 }
 
-// TODO: license checks, sanity checks for minimal_nickel_version. Anything else?
+/// Ways in which a package's subdirectory can fail to hold a package.
+#[derive(Debug, Serialize, thiserror::Error)]
+#[serde(tag = "problem", content = "subdir", rename_all = "snake_case")]
+pub enum SubdirError {
+    #[error("subdirectory `{0}` doesn't exist at the pinned commit")]
+    Missing(String),
+    #[error("subdirectory `{0}` doesn't contain a {MANIFEST_NAME}")]
+    NoManifest(String),
+    #[error("subdirectory `{0}` points outside of the repository")]
+    Escapes(String),
+}
+
+/// Locates the directory of a package within its fetched repository.
+///
+/// `subdir` comes from the package id and is supposed to be a normalized
+/// relative path, but we don't rely on that: since the repository's contents
+/// are untrusted, we also make sure that symlinks don't lead us out of it.
+///
+/// The returned path is canonical, so `repo_root` should be too if paths are
+/// going to be reported relative to it.
+pub fn package_dir(repo_root: &Path, subdir: &Path) -> Result<PathBuf, SubdirError> {
+    let name = subdir.display().to_string();
+    if subdir
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(SubdirError::Escapes(name));
+    }
+
+    // If we can't canonicalize the root, nothing inside it exists either.
+    let root = repo_root
+        .canonicalize()
+        .map_err(|_| SubdirError::Missing(name.clone()))?;
+    let dir = match repo_root.join(subdir).canonicalize() {
+        Ok(dir) if !dir.starts_with(&root) => return Err(SubdirError::Escapes(name)),
+        Ok(dir) if dir.is_dir() => dir,
+        _ => return Err(SubdirError::Missing(name)),
+    };
+    match dir.join(MANIFEST_NAME).canonicalize() {
+        Ok(manifest) if !manifest.starts_with(&root) => Err(SubdirError::Escapes(name)),
+        Ok(manifest) if manifest.is_file() => Ok(dir),
+        _ => Err(SubdirError::NoManifest(name)),
+    }
+}
+
+/// The first Nickel release that supports packages from the index.
+pub const OLDEST_PACKAGE_NICKEL_VERSION: SemVer = SemVer {
+    major: 1,
+    minor: 11,
+    patch: 0,
+    pre: String::new(),
+};
+
+/// The latest Nickel release that we know about.
+///
+/// This should be bumped whenever Nickel is released; in the meantime it
+/// can be overridden on the command line.
+pub const LATEST_NICKEL_VERSION: SemVer = SemVer {
+    major: 1,
+    minor: 14,
+    patch: 0,
+    pre: String::new(),
+};
+
+/// Configuration for the checks that we run on submitted packages.
+#[derive(Clone, Debug)]
+pub struct CheckConfig {
+    /// Whether to accept versions that are older than the newest published
+    /// version, as long as they're patch releases of a published minor version
+    /// and the newest on their own semver-compatible line.
+    pub allow_backports: bool,
+    /// Which licenses we accept.
+    pub licenses: LicensePolicy,
+    /// The latest released Nickel version; packages can't require anything newer.
+    pub latest_nickel_version: SemVer,
+    /// Limits for evaluating the package's entry point.
+    pub eval: EvalLimits,
+    /// Whether a package's commit must be tagged with its version. Otherwise,
+    /// being on the default branch is also fine.
+    pub require_tag: bool,
+    /// How many packages to fetch and check at once.
+    pub jobs: NonZeroUsize,
+}
+
+impl Default for CheckConfig {
+    fn default() -> Self {
+        Self {
+            allow_backports: false,
+            licenses: LicensePolicy::default(),
+            latest_nickel_version: LATEST_NICKEL_VERSION,
+            eval: EvalLimits::default(),
+            require_tag: false,
+            // unwrap: 4 isn't zero.
+            jobs: NonZeroUsize::new(4).unwrap(),
+        }
+    }
+}
+
+/// Where a submitted version fits among the previously published versions of its package.
+#[derive(Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum VersionCheck {
+    /// The version is newer than every previously published version.
+    Newest,
+    /// The version was already published.
+    Duplicate,
+    /// The version is older than some previously published version, but it's
+    /// a patch release of a published minor version, and the newest on its own
+    /// semver-compatible line.
+    Backport {
+        #[serde(serialize_with = "crate::json::display")]
+        newest: SemVer,
+    },
+    /// The version is older than some previously published version.
+    TooOld {
+        #[serde(serialize_with = "crate::json::display")]
+        newest: SemVer,
+    },
+}
+
+/// A description of the semver-compatible versions that `v` belongs to, like "1.x" or "0.3.x".
+fn compatible_line(v: &SemVer) -> String {
+    if v.major == 0 {
+        format!("0.{}.x", v.minor)
+    } else {
+        format!("{}.x", v.major)
+    }
+}
+
+impl VersionCheck {
+    /// Checks a new version against the previously published ones.
+    pub fn new(version: &SemVer, published: &[SemVer], config: &CheckConfig) -> Self {
+        if published.contains(version) {
+            return VersionCheck::Duplicate;
+        }
+        let Some(newest) = published.iter().max() else {
+            return VersionCheck::Newest;
+        };
+        if version > newest {
+            return VersionCheck::Newest;
+        }
+
+        // Backports are for fixing old releases, not for adding new ones, so
+        // they can only be patch releases.
+        let patches_published = published
+            .iter()
+            .any(|v| v.major == version.major && v.minor == version.minor);
+        let line = compatible_line(version);
+        let newest_on_line = published
+            .iter()
+            .filter(|v| compatible_line(v) == line)
+            .all(|v| v < version);
+        if config.allow_backports && patches_published && newest_on_line {
+            VersionCheck::Backport {
+                newest: newest.clone(),
+            }
+        } else {
+            VersionCheck::TooOld {
+                newest: newest.clone(),
+            }
+        }
+    }
+
+    pub fn is_good(&self) -> bool {
+        matches!(self, VersionCheck::Newest | VersionCheck::Backport { .. })
+    }
+
+    pub fn format(
+        &self,
+        f: &mut std::fmt::Formatter,
+        indent: &str,
+        version: &SemVer,
+    ) -> std::fmt::Result {
+        match self {
+            VersionCheck::Newest => {
+                writeln!(f, "{indent}✅ version {version} is the newest version")
+            }
+            VersionCheck::Duplicate => {
+                writeln!(f, "{indent}❌ version {version} was already published")
+            }
+            VersionCheck::Backport { newest } => writeln!(
+                f,
+                "{indent}✅ version {version} is a backport: it's the newest {} version (the newest overall is {newest})",
+                compatible_line(version)
+            ),
+            VersionCheck::TooOld { newest } => writeln!(
+                f,
+                "{indent}❌ version {version} is older than the published version {newest}"
+            ),
+        }
+    }
+}
+
+/// Checks a submitted package's version against the versions in the index.
+///
+/// `also_published` are versions of the same package that were submitted
+/// earlier in the same diff.
+pub fn check_version(
+    pkg: &Package,
+    index: &PackageIndex<Shared>,
+    also_published: &[SemVer],
+    config: &CheckConfig,
+) -> miette::Result<VersionCheck> {
+    let mut published: Vec<_> = index
+        .available_versions(&pkg.id.clone().into())
+        .into_diag()?
+        .collect();
+    published.extend_from_slice(also_published);
+    Ok(VersionCheck::new(&pkg.version, &published, config))
+}
+
 // TODO: handle failure to fetch here also
+#[derive(Serialize)]
 pub struct ManifestChecks {
-    package_version: SemVer,
-    manifest_version: SemVer,
+    fields: Vec<FieldCheck>,
+    license: LicenseChecks,
+    nickel_version: NickelVersionCheck,
+    entry_point: EvalCheck,
     dependencies: Vec<DependencyChecks>,
+    resolution: ResolutionCheck,
 }
 
 impl ManifestChecks {
     pub fn is_good(&self) -> bool {
-        self.package_version == self.manifest_version
+        self.fields.iter().all(|c| c.is_good())
+            && self.license.is_good()
+            && self.nickel_version.is_good()
+            && self.entry_point.is_good()
             && self.dependencies.iter().all(|d| d.is_good())
+            && self.resolution.is_good()
     }
 
     pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
-        if self.package_version == self.manifest_version {
-            writeln!(f, "{indent}✅ manifest version matches")?;
-        } else {
-            writeln!(
-                f,
-                "{indent}❌ index version {} doesn't match manifest version {}",
-                self.package_version, self.manifest_version
-            )?;
+        for field in &self.fields {
+            field.format(f, indent)?;
         }
+        self.license.format(f, indent)?;
+        self.nickel_version.format(f, indent)?;
+        self.entry_point.format(f, indent)?;
 
         if self.dependencies.is_empty() {
             writeln!(f, "{indent}✅ no dependencies to check")?;
@@ -139,13 +718,211 @@ impl ManifestChecks {
                 dep.format(f, indent)?;
             }
         }
+        self.resolution.format(f, indent)?;
 
         Ok(())
     }
 }
 
+/// A dependency that needs a newer version of Nickel than its dependent claims to need.
+#[derive(Serialize)]
+struct NeedsNewerNickel {
+    #[serde(serialize_with = "crate::json::index_dependency")]
+    dep: IndexDependency,
+    /// The oldest Nickel version supported by any version of `dep` that matches
+    /// the version requirement.
+    #[serde(serialize_with = "crate::json::display")]
+    requires: SemVer,
+}
+
+/// Sanity checks for a package's `minimal_nickel_version`.
+#[derive(Serialize)]
+pub struct NickelVersionCheck {
+    #[serde(serialize_with = "crate::json::display")]
+    minimal_nickel_version: SemVer,
+    #[serde(serialize_with = "crate::json::display")]
+    latest_nickel_version: SemVer,
+    dependencies: Vec<NeedsNewerNickel>,
+}
+
+impl NickelVersionCheck {
+    /// Checks `pkg`'s `minimal_nickel_version` against released Nickel versions,
+    /// and against the `minimal_nickel_version`s of its dependencies.
+    ///
+    /// `dependency_versions` contains the index entries for all versions of
+    /// each of `pkg`'s dependencies.
+    fn new(
+        pkg: &Package,
+        dependency_versions: &HashMap<Id, HashMap<SemVer, Package>>,
+        config: &CheckConfig,
+    ) -> Self {
+        let dependencies = pkg
+            .dependencies
+            .values()
+            .filter_map(|dep| {
+                // If a dependency has no matching versions, we report that elsewhere.
+                let requires = dependency_versions
+                    .get(&dep.id)?
+                    .iter()
+                    .filter(|(v, _)| dep.version.matches(v))
+                    .map(|(_, p)| &p.minimal_nickel_version)
+                    .min()?;
+                (requires > &pkg.minimal_nickel_version).then(|| NeedsNewerNickel {
+                    dep: dep.clone(),
+                    requires: requires.clone(),
+                })
+            })
+            .collect();
+
+        Self {
+            minimal_nickel_version: pkg.minimal_nickel_version.clone(),
+            latest_nickel_version: config.latest_nickel_version.clone(),
+            dependencies,
+        }
+    }
+
+    fn is_released(&self) -> bool {
+        self.minimal_nickel_version <= self.latest_nickel_version
+    }
+
+    fn supports_packages(&self) -> bool {
+        self.minimal_nickel_version >= OLDEST_PACKAGE_NICKEL_VERSION
+    }
+
+    pub fn is_good(&self) -> bool {
+        self.is_released() && self.supports_packages() && self.dependencies.is_empty()
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        let v = &self.minimal_nickel_version;
+        if !self.is_released() {
+            writeln!(
+                f,
+                "{indent}❌ minimal_nickel_version {v} is newer than the latest Nickel release ({})",
+                self.latest_nickel_version
+            )?;
+        } else if !self.supports_packages() {
+            writeln!(
+                f,
+                "{indent}❌ minimal_nickel_version {v} is too old: packages need at least Nickel {OLDEST_PACKAGE_NICKEL_VERSION}"
+            )?;
+        } else {
+            writeln!(
+                f,
+                "{indent}✅ minimal_nickel_version {v} is a valid Nickel version"
+            )?;
+        }
+
+        for d in &self.dependencies {
+            writeln!(
+                f,
+                "{indent}❌ dependency {} {} needs at least Nickel {}, but minimal_nickel_version is {v}",
+                d.dep.id, d.dep.version, d.requires
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A comparison between a field of the index entry and the same field of the manifest.
+#[derive(Serialize)]
+pub struct FieldCheck {
+    field: &'static str,
+    index: String,
+    manifest: String,
+}
+
+impl FieldCheck {
+    fn new(field: &'static str, index: impl ToString, manifest: impl ToString) -> Self {
+        Self {
+            field,
+            index: index.to_string(),
+            manifest: manifest.to_string(),
+        }
+    }
+
+    pub fn is_good(&self) -> bool {
+        self.index == self.manifest
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        let field = self.field;
+        if self.is_good() {
+            writeln!(f, "{indent}✅ manifest {field} matches")
+        } else {
+            writeln!(
+                f,
+                "{indent}❌ index {field} `{}` doesn't match manifest {field} `{}`",
+                self.index, self.manifest
+            )
+        }
+    }
+}
+
+/// Renders a list of strings for comparison, like `["a", "b"]`.
+fn quoted_list(items: &[String]) -> String {
+    format!("{items:?}")
+}
+
+/// Compares all the metadata in an index entry with the metadata in the package's manifest.
+fn compare_metadata(pkg: &Package, manifest: &ManifestFile) -> Vec<FieldCheck> {
+    let mut index_deps = pkg
+        .dependencies
+        .iter()
+        .map(|(name, dep)| format!("{name}: {} {}", dep.id, dep.version))
+        .collect::<Vec<_>>();
+    let mut manifest_deps = manifest
+        .dependencies
+        .iter()
+        .map(|(name, dep)| match dep {
+            Dependency::Index(dep) => format!("{name}: {} {}", dep.id, dep.version),
+            Dependency::Git(dep) => format!("{name}: git {}", dep.url.to_bstring()),
+            Dependency::Path(path) => format!("{name}: path {}", path.display()),
+        })
+        .collect::<Vec<_>>();
+    index_deps.sort();
+    manifest_deps.sort();
+
+    vec![
+        FieldCheck::new("version", &pkg.version, &manifest.version),
+        FieldCheck::new(
+            "minimal_nickel_version",
+            &pkg.minimal_nickel_version,
+            &manifest.minimal_nickel_version,
+        ),
+        FieldCheck::new(
+            "authors",
+            quoted_list(&pkg.authors),
+            quoted_list(&manifest.authors),
+        ),
+        FieldCheck::new(
+            "description",
+            format!("{:?}", pkg.description),
+            format!("{:?}", manifest.description),
+        ),
+        FieldCheck::new(
+            "keywords",
+            quoted_list(&pkg.keywords),
+            quoted_list(&manifest.keywords),
+        ),
+        FieldCheck::new(
+            "license",
+            format!("{:?}", pkg.license),
+            format!("{:?}", manifest.license),
+        ),
+        FieldCheck::new(
+            "dependencies",
+            index_deps.join(", "),
+            manifest_deps.join(", "),
+        ),
+    ]
+}
+
+#[derive(Serialize)]
 pub struct DependencyChecks {
+    #[serde(serialize_with = "crate::json::index_dependency")]
     dep: IndexDependency,
+    #[serde(serialize_with = "crate::json::display_seq")]
     known_versions: Vec<SemVer>,
     has_match: bool,
 }
@@ -178,6 +955,93 @@ impl DependencyChecks {
     }
 }
 
+/// A version of an index package, as chosen by the resolver.
+#[derive(Serialize)]
+struct ResolvedPackage {
+    #[serde(serialize_with = "crate::json::display")]
+    id: Id,
+    #[serde(serialize_with = "crate::json::display")]
+    version: SemVer,
+}
+
+/// The result of resolving a package's whole dependency tree against the index.
+#[derive(Serialize)]
+#[serde(tag = "outcome", content = "details", rename_all = "snake_case")]
+enum ResolutionCheck {
+    /// All the index packages in the dependency tree, sorted by id and version.
+    Resolved(Vec<ResolvedPackage>),
+    Failed(String),
+}
+
+/// Runs the package manager's resolver on a manifest. Returns `None` if there
+/// are no dependencies to resolve.
+pub fn resolve(
+    manifest: &ManifestFile,
+    index_config: &Config,
+) -> miette::Result<Option<Resolution>> {
+    if manifest.dependencies.is_empty() {
+        return Ok(None);
+    }
+
+    // The resolver wants its own handle to the index. Opening another shared
+    // handle is fine, since we're only reading.
+    let index = PackageIndex::shared(index_config.clone()).into_diag()?;
+    let config = with_git_replacements(index_config, manifest);
+    let snapshot = Snapshot::new(&config, &manifest.parent_dir, manifest).into_diag()?;
+    let resolution = resolve::resolve(manifest, snapshot, index, config).into_diag()?;
+    Ok(Some(resolution))
+}
+
+impl ResolutionCheck {
+    /// Reports the outcome of [`resolve`].
+    ///
+    /// This catches problems that [`DependencyChecks`] can't see, because they
+    /// only show up when looking at the whole dependency tree, like
+    /// transitive dependencies with unsatisfiable requirements.
+    pub fn new(resolution: &miette::Result<Option<Resolution>>) -> Self {
+        let resolution = match resolution {
+            Ok(Some(resolution)) => resolution,
+            Ok(None) => return ResolutionCheck::Resolved(Vec::new()),
+            Err(e) => return ResolutionCheck::Failed(e.to_string()),
+        };
+
+        let mut resolved: Vec<_> = resolution
+            .index_packages
+            .iter()
+            .flat_map(|(id, versions)| {
+                versions.iter().map(move |version| ResolvedPackage {
+                    id: id.clone(),
+                    version: version.clone(),
+                })
+            })
+            .collect();
+        resolved.sort_by(|a, b| (&a.id, &a.version).cmp(&(&b.id, &b.version)));
+        ResolutionCheck::Resolved(resolved)
+    }
+
+    pub fn is_good(&self) -> bool {
+        matches!(self, ResolutionCheck::Resolved(_))
+    }
+
+    pub fn format(&self, f: &mut std::fmt::Formatter, indent: &str) -> std::fmt::Result {
+        match self {
+            // There's nothing to say if there were no dependencies.
+            ResolutionCheck::Resolved(resolved) if resolved.is_empty() => Ok(()),
+            ResolutionCheck::Resolved(resolved) => {
+                writeln!(f, "{indent}✅ dependencies resolve to:")?;
+                let indent = &format!("{indent}- ");
+                for r in resolved {
+                    writeln!(f, "{indent}{} {}", r.id, r.version)?;
+                }
+                Ok(())
+            }
+            ResolutionCheck::Failed(e) => {
+                writeln!(f, "{indent}❌ failed to resolve dependencies: {e}")
+            }
+        }
+    }
+}
+
 // This error handling is inconvenient because nickel's errors aren't Send + Sync.
 // Maybe they should be? Or there should be convenience wrappers?
 pub trait IntoDiagnostic<T> {
@@ -200,32 +1064,63 @@ impl<T> IntoDiagnostic<T> for Result<T, nickel_lang_package::error::Error> {
     }
 }
 
-/// Runs sanity checks against a package manifest.
+/// Runs sanity checks against a package manifest, and checks that the
+/// package's entry point evaluates.
+///
+/// `repo_root` is the directory that the package's repository was fetched to,
+/// and `package_dir` is the package's directory inside it, as found by
+/// [`package_dir`]. `index_config` is the configuration that `index` was
+/// opened with.
 pub fn check_manifest(
     pkg: &Package,
-    path: &Path,
+    repo_root: &Path,
+    package_dir: &Path,
     index: &PackageIndex<Shared>,
+    index_config: &Config,
+    config: &CheckConfig,
 ) -> miette::Result<ManifestChecks> {
-    let mut path = path.to_owned();
-    path.push(MANIFEST_NAME);
+    let path = package_dir.join(MANIFEST_NAME);
 
+    // We evaluate the manifest in this process, so make sure that it can't
+    // read anything it shouldn't. If it doesn't parse, evaluating it will say so.
+    let no_packages = PackageMap::default();
+    if let Err(ImportError::Forbidden(import)) =
+        eval::check_imports(&path, package_dir, &no_packages)
+    {
+        bail!("{MANIFEST_NAME} imports `{import}`, which is outside of the package");
+    }
     // TODO: report manifest eval errors better
-    // FIXME: use the subdir here if necessary
     let manifest = ManifestFile::from_path(&path).into_diag()?;
 
     let mut dependencies = Vec::new();
+    let mut dependency_versions = HashMap::new();
     for dep in pkg.dependencies.values() {
-        let available: Vec<_> = index.available_versions(&dep.id).into_diag()?.collect();
+        let all_versions = index.all_versions(&dep.id).into_diag()?;
+        let mut available: Vec<_> = all_versions.keys().cloned().collect();
+        available.sort();
         dependencies.push(DependencyChecks {
             dep: dep.clone(),
             has_match: available.iter().any(|v| dep.version.matches(v)),
             known_versions: available,
         });
+        dependency_versions.insert(dep.id.clone(), all_versions);
     }
 
+    let resolution = resolve(&manifest, index_config);
+    let entry_point = match &resolution {
+        Ok(resolution) => match eval::package_map(resolution.as_ref(), &manifest) {
+            Ok(map) => EvalCheck::new(package_dir, &map, &config.eval),
+            Err(e) => EvalCheck::DependenciesFailed(e.to_string()),
+        },
+        Err(e) => EvalCheck::DependenciesFailed(e.to_string()),
+    };
+
     Ok(ManifestChecks {
-        package_version: pkg.version.clone(),
-        manifest_version: manifest.version,
+        fields: compare_metadata(pkg, &manifest),
+        license: LicenseChecks::new(&pkg.license, repo_root, package_dir, &config.licenses),
+        nickel_version: NickelVersionCheck::new(pkg, &dependency_versions, config),
+        entry_point,
+        resolution: ResolutionCheck::new(&resolution),
         dependencies,
     })
 }
@@ -235,9 +1130,16 @@ mod tests {
     use std::path::PathBuf;
 
     use gix::ObjectId;
-    use nickel_lang_package::version::SemVer;
+    use nickel_lang_core::identifier::Ident;
+    use nickel_lang_git::Target;
+    use nickel_lang_package::{
+        GitDependency,
+        index::PreciseId,
+        version::{SemVer, VersionReq},
+    };
 
     use super::*;
+    use crate::test_repo;
 
     const SAMPLE_DIFF: &str = r#"
 diff --git a/github/nickel-lang/nickel-schemastore b/github/nickel-lang/nickel-schemastore
@@ -247,6 +1149,16 @@ index df1cd2a..2229806 100644
 @@ -1 +1,2 @@
  {"id":{"github":{"org":"nickel-lang","name":"nickel-schemastore","commit":"3ac728792d4a71f53897b185445b77029c3ce245"}},"version":{"major":0,"minor":1,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{},"authors":["Théophane Hufschmitt","Yann Hamdaoui <yann.hamdaoui@tweag.io>"],"description":"A nickel package containing contracts autogenerated from the Schemastore JSON Schema repository via json-schema-to-nickel.","keywords":["schemastore","schemas","json-schema","contracts"],"license":"MIT","v":0}
 +{"id":{"github":{"org":"nickel-lang","name":"nickel-schemastore","commit":"5b5edcba47eb5f957a34a6224b3d9b976a4fc911"}},"version":{"major":0,"minor":2,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{},"authors":["Théophane Hufschmitt","Yann Hamdaoui <yann.hamdaoui@tweag.io>"],"description":"Nickel contracts autogenerated from the Schemastore JSON Schema repository via json-schema-to-nickel","keywords":["schemastore","schemas","json-schema","contracts"],"license":"MIT","v":0}
+"#;
+
+    const SAMPLE_YANK_DIFF: &str = r#"
+diff --git a/github/nickel-lang/nickel-schemastore b/github/nickel-lang/nickel-schemastore
+index df1cd2a..2229806 100644
+--- a/github/nickel-lang/nickel-schemastore
++++ b/github/nickel-lang/nickel-schemastore
+@@ -1 +1 @@
+-{"id":{"github":{"org":"nickel-lang","name":"nickel-schemastore","commit":"3ac728792d4a71f53897b185445b77029c3ce245"}},"version":{"major":0,"minor":1,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{},"authors":["Théophane Hufschmitt","Yann Hamdaoui <yann.hamdaoui@tweag.io>"],"description":"A nickel package containing contracts autogenerated from the Schemastore JSON Schema repository via json-schema-to-nickel.","keywords":["schemastore","schemas","json-schema","contracts"],"license":"MIT","v":0}
++{"id":{"github":{"org":"nickel-lang","name":"nickel-schemastore","commit":"3ac728792d4a71f53897b185445b77029c3ce245"}},"version":{"major":0,"minor":1,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{},"authors":["Théophane Hufschmitt","Yann Hamdaoui <yann.hamdaoui@tweag.io>"],"description":"A nickel package containing contracts autogenerated from the Schemastore JSON Schema repository via json-schema-to-nickel.","keywords":["schemastore","schemas","json-schema","contracts"],"license":"MIT","v":0,"yanked":true}
 "#;
 
     const SAMPLE_DIFF_WITH_SUBDIR: &str = r#"
@@ -259,21 +1171,640 @@ index 0000000..17e1150
 +{"id":{"github":{"org":"nickel-lang","name":"json-schema-to-nickel","path":"lib","commit":"7d7c007c1de43aa448df633ddbcb33b54385d8a0"}},"version":{"major":0,"minor":1,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":12,"patch":0,"pre":""},"dependencies":{},"authors":["The json-schema-to-nickel authors"],"description":"A library of predicates for JSON schema","keywords":[],"license":"","v":0}
 "#;
 
+    const SAMPLE_DIFF_WITH_DEPENDENCY: &str = r#"
+diff --git a/github/nickel-lang/example b/github/nickel-lang/example
+new file mode 100644
+index 0000000..17e1150
+--- /dev/null
++++ b/github/nickel-lang/example
+@@ -0,0 +1 @@
++{"id":{"github":{"org":"nickel-lang","name":"example","commit":"7d7c007c1de43aa448df633ddbcb33b54385d8a0"}},"version":{"major":1,"minor":0,"patch":0,"pre":""},"minimal_nickel_version":{"major":1,"minor":11,"patch":0,"pre":""},"dependencies":{"schemas":{"type":"github","org":"nickel-lang","name":"nickel-schemastore","req":{"Compatible":{"major":0,"minor":2}}}},"authors":[],"description":"An example","keywords":[],"license":"MIT","v":0}
+"#;
+
+    fn added_packages(diff: &str) -> Vec<AddedPackage> {
+        let patches = Patch::from_multiple(diff).unwrap();
+        let (changes, errors) = changed_packages(patches);
+        assert!(errors.is_empty());
+        changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Added(added) => added,
+                Change::Yanked(_) => panic!("unexpected yank"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_version_check() {
+        let published = [
+            SemVer::new(0, 1, 0),
+            SemVer::new(0, 2, 0),
+            SemVer::new(1, 0, 0),
+            SemVer::new(1, 1, 0),
+            SemVer::new(2, 0, 0),
+        ];
+        let strict = CheckConfig::default();
+        let lenient = CheckConfig {
+            allow_backports: true,
+            ..CheckConfig::default()
+        };
+        let check = |v, config| VersionCheck::new(&v, &published, config);
+
+        assert!(matches!(
+            check(SemVer::new(2, 0, 1), &strict),
+            VersionCheck::Newest
+        ));
+        assert!(matches!(
+            check(SemVer::new(1, 1, 0), &lenient),
+            VersionCheck::Duplicate
+        ));
+        assert!(matches!(
+            check(SemVer::new(1, 1, 1), &strict),
+            VersionCheck::TooOld { .. }
+        ));
+        assert!(matches!(
+            check(SemVer::new(1, 1, 1), &lenient),
+            VersionCheck::Backport { .. }
+        ));
+        assert!(matches!(
+            check(SemVer::new(0, 1, 1), &lenient),
+            VersionCheck::Backport { .. }
+        ));
+        // 1.0.1 is older than 1.1.0, which is on the same line.
+        assert!(matches!(
+            check(SemVer::new(1, 0, 1), &lenient),
+            VersionCheck::TooOld { .. }
+        ));
+        // 1.2.0 would be the newest 1.x version, but it's a new minor version.
+        assert!(matches!(
+            check(SemVer::new(1, 2, 0), &lenient),
+            VersionCheck::TooOld { .. }
+        ));
+        assert!(matches!(
+            check(SemVer::new(0, 3, 0), &lenient),
+            VersionCheck::TooOld { .. }
+        ));
+        assert!(matches!(
+            VersionCheck::new(&SemVer::new(0, 1, 0), &[], &strict),
+            VersionCheck::Newest
+        ));
+    }
+
+    #[test]
+    fn test_compare_metadata() {
+        let pkg = added_packages(SAMPLE_DIFF).remove(0).pkg;
+        let mut manifest = ManifestFile {
+            parent_dir: PathBuf::default(),
+            name: "schemastore".into(),
+            version: pkg.version.clone(),
+            minimal_nickel_version: pkg.minimal_nickel_version.clone(),
+            dependencies: Default::default(),
+            authors: pkg.authors.clone(),
+            description: pkg.description.clone(),
+            keywords: pkg.keywords.clone(),
+            license: pkg.license.clone(),
+        };
+        assert!(
+            compare_metadata(&pkg, &manifest)
+                .iter()
+                .all(|c| c.is_good())
+        );
+
+        manifest.license = "Apache-2.0".to_owned();
+        manifest.keywords.pop();
+        let bad: Vec<_> = compare_metadata(&pkg, &manifest)
+            .into_iter()
+            .filter(|c| !c.is_good())
+            .collect();
+        assert_eq!(bad.len(), 2);
+        assert_eq!(bad[0].field, "keywords");
+        assert_eq!(bad[1].field, "license");
+        assert_eq!(bad[1].index, "\"MIT\"");
+        assert_eq!(bad[1].manifest, "\"Apache-2.0\"");
+    }
+
+    #[test]
+    fn test_nickel_version_check() {
+        let mut pkg = added_packages(SAMPLE_DIFF_WITH_DEPENDENCY).remove(0).pkg;
+        let dep = added_packages(SAMPLE_DIFF).remove(0).pkg;
+        let config = CheckConfig::default();
+
+        let dep_id = Id::from(dep.id.clone());
+        let mut dep_versions = HashMap::new();
+        dep_versions.insert(
+            dep_id.clone(),
+            [(dep.version.clone(), dep.clone())].into_iter().collect(),
+        );
+        let check =
+            |pkg: &Package, deps: &HashMap<_, _>| NickelVersionCheck::new(pkg, deps, &config);
+
+        assert!(check(&pkg, &dep_versions).is_good());
+        assert!(check(&pkg, &HashMap::new()).is_good());
+
+        pkg.minimal_nickel_version = SemVer::new(1, 10, 0);
+        let c = check(&pkg, &HashMap::new());
+        assert!(c.is_released() && !c.supports_packages());
+
+        pkg.minimal_nickel_version = SemVer::new(99, 0, 0);
+        let c = check(&pkg, &HashMap::new());
+        assert!(!c.is_released() && c.supports_packages());
+
+        // The dependency needs 1.11, so this package can't claim to support anything older.
+        pkg.minimal_nickel_version = SemVer::new(1, 11, 0);
+        dep_versions
+            .get_mut(&dep_id)
+            .unwrap()
+            .values_mut()
+            .for_each(|p| {
+                p.minimal_nickel_version = SemVer::new(1, 12, 0);
+            });
+        let c = check(&pkg, &dep_versions);
+        assert!(!c.is_good());
+        assert_eq!(c.dependencies.len(), 1);
+        assert_eq!(c.dependencies[0].requires, SemVer::new(1, 12, 0));
+    }
+
+    /// A package manager config that keeps everything in `cache`, and
+    /// fetches from the mirror in `mirrors` instead of from github.
+    fn mirrored_config(cache: &Path, mirrors: &Path) -> Config {
+        let config = Config::new().unwrap().with_cache_dir(cache.to_owned());
+        with_mirror(config, &format!("file://{}/", mirrors.display())).unwrap()
+    }
+
+    #[test]
+    fn test_with_mirror() {
+        let config = with_mirror(Config::new().unwrap(), "file:///srv/mirrors/").unwrap();
+        assert_eq!(
+            config.index_url.to_bstring(),
+            "file:///srv/mirrors/nickel-lang/nickel-mine"
+        );
+        let pkg = index_package("pkg", "1.0.0", &[]);
+        assert_eq!(
+            pkg.id.download_spec(&config).url.to_bstring(),
+            "file:///srv/mirrors/org/pkg"
+        );
+    }
+
+    #[test]
+    fn test_fetch_from_mirror() {
+        let mirrors = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(&mirrors.path().join("org/pkg"));
+        let commit = test_repo::commit(&repo, "a@example.com", "main.ncl", "1");
+
+        let pkg_at = |commit| {
+            let mut pkg = index_package("pkg", "1.0.0", &[]);
+            let PreciseId::Github { commit: id, .. } = &mut pkg.id;
+            *id = commit;
+            pkg
+        };
+        let cache = tempfile::tempdir().unwrap();
+        let config = mirrored_config(cache.path(), mirrors.path());
+
+        let dest = tempfile::tempdir().unwrap();
+        fetch(&pkg_at(commit), dest.path(), &config).unwrap();
+        let contents = std::fs::read_to_string(dest.path().join("main.ncl")).unwrap();
+        assert_eq!(contents, "1");
+
+        // The mirror has to actually have the commit.
+        let missing = "7d7c007c1de43aa448df633ddbcb33b54385d8a0".parse().unwrap();
+        let pkg = pkg_at(missing);
+        let dest = tempfile::tempdir().unwrap();
+        assert!(fetch(&pkg, dest.path(), &config).is_err());
+    }
+
+    #[test]
+    fn test_index_from_mirror() {
+        let mirrors = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(&mirrors.path().join(INDEX_REPO));
+        let pkg = index_package("pkg", "1.0.0", &[]);
+        test_repo::commit(
+            &repo,
+            "a@example.com",
+            "github/org/pkg",
+            &canonical_line(&pkg),
+        );
+
+        // There's no index in the cache, and nothing to refresh it from but the mirror.
+        let cache = tempfile::tempdir().unwrap();
+        let config = mirrored_config(cache.path(), mirrors.path());
+        let index = PackageIndex::refreshed(config).unwrap();
+        let id = "github:org/pkg".parse().unwrap();
+        assert!(index.has_version(&id, &SemVer::new(1, 0, 0)).unwrap());
+    }
+
+    #[test]
+    fn test_package_dir() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(root.join(MANIFEST_NAME), "").unwrap();
+        std::fs::create_dir_all(root.join("lib/empty")).unwrap();
+        std::fs::write(root.join("lib").join(MANIFEST_NAME), "").unwrap();
+        std::fs::write(outside.path().join(MANIFEST_NAME), "").unwrap();
+
+        let dir = |subdir: &str| package_dir(root, Path::new(subdir));
+        let canonical = |subdir: &str| root.join(subdir).canonicalize().unwrap();
+
+        assert_eq!(dir("").unwrap(), canonical(""));
+        assert_eq!(dir("lib").unwrap(), canonical("lib"));
+        assert!(matches!(dir("nope"), Err(SubdirError::Missing(_))));
+        assert!(matches!(dir("lib/empty"), Err(SubdirError::NoManifest(_))));
+        assert!(matches!(dir("lib/.."), Err(SubdirError::Escapes(_))));
+        assert!(matches!(dir("../x"), Err(SubdirError::Escapes(_))));
+
+        // A manifest that isn't a file doesn't count.
+        std::fs::create_dir(root.join("lib/empty").join(MANIFEST_NAME)).unwrap();
+        assert!(matches!(dir("lib/empty"), Err(SubdirError::NoManifest(_))));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+
+            // Symlinks within the repository are fine...
+            symlink(root.join("lib"), root.join("link")).unwrap();
+            assert_eq!(dir("link").unwrap(), canonical("lib"));
+
+            // ...but not ones that lead outside of it, whether they're the
+            // directory itself or just the manifest.
+            symlink(outside.path(), root.join("escape")).unwrap();
+            assert!(matches!(dir("escape"), Err(SubdirError::Escapes(_))));
+
+            std::fs::create_dir(root.join("sneaky")).unwrap();
+            symlink(
+                outside.path().join(MANIFEST_NAME),
+                root.join("sneaky").join(MANIFEST_NAME),
+            )
+            .unwrap();
+            assert!(matches!(dir("sneaky"), Err(SubdirError::Escapes(_))));
+        }
+    }
+
+    fn index_package(name: &str, version: &str, deps: &[(&str, &str)]) -> Package {
+        let deps: serde_json::Map<_, _> = deps
+            .iter()
+            .map(|(dep, req)| {
+                (
+                    dep.to_string(),
+                    serde_json::json!({
+                        "type": "github",
+                        "org": "org",
+                        "name": dep,
+                        "req": req.parse::<VersionReq>().unwrap(),
+                    }),
+                )
+            })
+            .collect();
+        let format = serde_json::json!({
+            "id": {"github": {"org": "org", "name": name, "commit": "7d7c007c1de43aa448df633ddbcb33b54385d8a0"}},
+            "version": version.parse::<SemVer>().unwrap(),
+            "minimal_nickel_version": SemVer::new(1, 11, 0),
+            "dependencies": deps,
+            "authors": [],
+            "description": "",
+            "keywords": [],
+            "license": "MIT",
+            "v": 0,
+        });
+        serde_json::from_value::<PackageFormat>(format)
+            .unwrap()
+            .into()
+    }
+
+    fn manifest_with_deps(dir: &Path, deps: &[(&str, &str)]) -> ManifestFile {
+        ManifestFile {
+            parent_dir: dir.to_owned(),
+            name: "root".into(),
+            version: SemVer::new(1, 0, 0),
+            minimal_nickel_version: SemVer::new(1, 11, 0),
+            dependencies: deps
+                .iter()
+                .map(|(dep, req)| {
+                    (
+                        Ident::new(dep),
+                        Dependency::Index(IndexDependency {
+                            id: format!("github:org/{dep}").parse().unwrap(),
+                            version: req.parse().unwrap(),
+                        }),
+                    )
+                })
+                .collect(),
+            authors: Vec::new(),
+            description: String::new(),
+            keywords: Vec::new(),
+            license: "MIT".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_resolution_check() {
+        let cache = tempfile::tempdir().unwrap();
+        let config = Config::new()
+            .unwrap()
+            .with_cache_dir(cache.path().to_owned());
+        {
+            let mut index = PackageIndex::exclusive(config.clone()).unwrap();
+            index
+                .save(index_package("a", "1.0.0", &[("b", "2.0")]))
+                .unwrap();
+            index.save(index_package("b", "1.0.0", &[])).unwrap();
+            index
+                .save(index_package("c", "1.0.0", &[("b", "1.0")]))
+                .unwrap();
+        }
+
+        let check =
+            |deps| ResolutionCheck::new(&resolve(&manifest_with_deps(cache.path(), deps), &config));
+
+        let ResolutionCheck::Resolved(resolved) = check(&[("c", "1.0")]) else {
+            panic!("expected resolution to succeed");
+        };
+        let resolved: Vec<_> = resolved
+            .iter()
+            .map(|r| format!("{} {}", r.id, r.version))
+            .collect();
+        assert_eq!(resolved, ["github:org/b 1.0.0", "github:org/c 1.0.0"]);
+
+        // `a` exists, but it needs a version of `b` that doesn't.
+        assert!(!check(&[("a", "1.0")]).is_good());
+        assert!(check(&[]).is_good());
+    }
+
+    #[test]
+    fn test_dependencies_from_mirror() {
+        let mirrors = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(&mirrors.path().join("org/b"));
+        let commit = test_repo::commit(&repo, "a@example.com", "main.ncl", "1");
+
+        let cache = tempfile::tempdir().unwrap();
+        let config = mirrored_config(cache.path(), mirrors.path());
+        {
+            let mut b = index_package("b", "1.0.0", &[]);
+            let PreciseId::Github { commit: id, .. } = &mut b.id;
+            *id = commit;
+            let mut index = PackageIndex::exclusive(config.clone()).unwrap();
+            index.save(b).unwrap();
+        }
+
+        // There's no org/b on github, so this only works if it uses the mirror.
+        let manifest = manifest_with_deps(cache.path(), &[("b", "1.0")]);
+        let resolution = resolve(&manifest, &config).unwrap();
+        let map = eval::package_map(resolution.as_ref(), &manifest).unwrap();
+        let b = &map.top_level[&Ident::new("b")];
+        assert_eq!(std::fs::read_to_string(b.join("main.ncl")).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_git_dependencies_from_mirror() {
+        let mirrors = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(&mirrors.path().join("org/git-dep"));
+        test_repo::commit(
+            &repo,
+            "a@example.com",
+            MANIFEST_NAME,
+            r#"{ name = "git-dep", version = "1.0.0", minimal_nickel_version = "1.11.0", authors = [], description = "hi" }"#,
+        );
+
+        let cache = tempfile::tempdir().unwrap();
+        let config = mirrored_config(cache.path(), mirrors.path());
+        let mut manifest = manifest_with_deps(cache.path(), &[]);
+        let dep = GitDependency {
+            url: "https://github.com/org/git-dep.git".try_into().unwrap(),
+            target: Target::Head,
+            path: PathBuf::new(),
+        };
+        manifest
+            .dependencies
+            .insert(Ident::new("git_dep"), Dependency::Git(dep));
+
+        // There's no org/git-dep on github, so this only works if it uses the mirror.
+        assert!(resolve(&manifest, &config).unwrap().is_some());
+    }
+
     #[test]
     fn test_changed_packages() {
-        let patches = Patch::from_multiple(SAMPLE_DIFF).unwrap();
-        let packages = changed_packages(patches).unwrap();
+        let packages = added_packages(SAMPLE_DIFF);
         assert_eq!(packages.len(), 1);
-        assert_eq!(packages[0].version, SemVer::new(0, 2, 0));
+        assert_eq!(packages[0].pkg.version, SemVer::new(0, 2, 0));
+        assert_eq!(packages[0].path, "github/nickel-lang/nickel-schemastore");
+        assert_eq!(packages[0].line, 2);
+    }
+
+    #[test]
+    fn test_yanks() {
+        let patches = Patch::from_multiple(SAMPLE_YANK_DIFF).unwrap();
+        let (changes, errors) = changed_packages(patches);
+        assert!(errors.is_empty());
+        let [Change::Yanked(yanked)] = changes.as_slice() else {
+            panic!("expected a single yank");
+        };
+        assert_eq!(yanked.pkg.version, SemVer::new(0, 1, 0));
+        assert_eq!(yanked.line, 1);
+
+        // Changing anything else about the entry isn't a yank, and neither is
+        // writing the marker in any other way.
+        let variants = [
+            r#""license":"GPL-3.0","v":0,"yanked":true"#,
+            r#""license":"MIT","v":0,"yanked": true"#,
+            r#""license":"MIT","yanked":true,"v":0"#,
+        ];
+        for variant in variants {
+            let diff = SAMPLE_YANK_DIFF.replace(r#""license":"MIT","v":0,"yanked":true"#, variant);
+            assert_ne!(diff, SAMPLE_YANK_DIFF);
+            let patches = Patch::from_multiple(&diff).unwrap();
+            assert!(
+                matches!(
+                    changed_packages(patches).1.as_slice(),
+                    [Error::Deletion { .. }]
+                ),
+                "{variant}"
+            );
+        }
+
+        // Neither is un-yanking.
+        let line = |prefix| {
+            SAMPLE_YANK_DIFF
+                .lines()
+                .find_map(|l| l.strip_prefix(prefix).filter(|l| l.starts_with('{')))
+                .unwrap()
+        };
+        let (old, new) = (line("-"), line("+"));
+        let diff = SAMPLE_YANK_DIFF
+            .replace(&format!("-{old}"), &format!("-{new}"))
+            .replacen(&format!("+{new}"), &format!("+{old}"), 1);
+        let patches = Patch::from_multiple(&diff).unwrap();
+        assert!(matches!(
+            changed_packages(patches).1.as_slice(),
+            [Error::Deletion { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_dependents() {
+        let index = tempfile::tempdir().unwrap();
+        let dir = index.path().join("github/org");
+        std::fs::create_dir_all(&dir).unwrap();
+        let line = |pkg: Package| serde_json::to_string(&PackageFormat::from(pkg)).unwrap();
+        std::fs::write(
+            dir.join("a"),
+            format!(
+                "{}\n{}\n",
+                line(index_package("a", "1.0.0", &[("b", "1.0")])),
+                line(index_package("a", "2.0.0", &[("b", "2.0")]))
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join("b"), line(index_package("b", "1.1.0", &[]))).unwrap();
+
+        let b: Id = "github:org/b".parse().unwrap();
+        let found = dependents(index.path(), &b, &SemVer::new(1, 1, 0)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].version, SemVer::new(1, 0, 0));
+        assert!(
+            dependents(index.path(), &b, &SemVer::new(3, 0, 0))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_error() {
+        let diff = SAMPLE_DIFF.replace(r#""v":0"#, r#""v":}"#);
+        let patches = Patch::from_multiple(&diff).unwrap();
+        let (_, errors) = changed_packages(patches);
+        let [e] = errors.as_slice() else {
+            panic!("expected one error, got {errors:?}");
+        };
+        let Error::Deserialize { src, span, .. } = e else {
+            panic!("expected a deserialization error, got {e}");
+        };
+        assert_eq!(src.path, "github/nickel-lang/nickel-schemastore");
+        assert_eq!(src.line, 2);
+
+        let rendered = e.render();
+        let column = span.offset() + 1;
+        assert!(rendered.contains(&format!(
+            "[github/nickel-lang/nickel-schemastore:2:{column}]"
+        )));
+        assert!(rendered.contains("here"));
+    }
+
+    #[test]
+    fn test_line_endings() {
+        // gitpatch treats "\r\n" as a line ending, so only tree diffs can
+        // give us a line that ends with "\r".
+        let mut patch = Patch::from_single(SAMPLE_DIFF.trim_start()).unwrap();
+        let gitpatch::Line::Add(line) = patch.hunks[0].lines[1] else {
+            panic!("expected an added line");
+        };
+        let crlf = format!("{line}\r");
+        patch.hunks[0].lines[1] = gitpatch::Line::Add(&crlf);
+        let (changes, errors) = changed_packages(vec![patch]);
+        assert!(changes.is_empty());
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::LineEnding { line: 2, .. }]
+        ));
+
+        let no_newline = format!("{}\n\\ No newline at end of file\n", SAMPLE_DIFF.trim_end());
+        let (changes, errors) = changed_packages(Patch::from_multiple(&no_newline).unwrap());
+        assert_eq!(changes.len(), 1);
+        let [e] = errors.as_slice() else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(
+            e.location(),
+            Some(("github/nickel-lang/nickel-schemastore", 2))
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let path = "github/nickel-lang/nickel-schemastore";
+        // Replacing the first entry leaves a new one in its place.
+        let modified = SAMPLE_DIFF.replace("@@ -1 +1,2 @@\n ", "@@ -1 +1 @@\n-");
+        let (_, errors) = changed_packages(Patch::from_multiple(&modified).unwrap());
+        let locations: Vec<_> = errors.iter().map(Error::location).collect();
+        assert_eq!(locations, [Some((path, 1))]);
+
+        // Packages in the wrong file are reported where they were added.
+        let moved = SAMPLE_DIFF.replace(
+            "+{\"id\":{\"github\":{\"org\":\"nickel-lang\"",
+            "+{\"id\":{\"github\":{\"org\":\"elsewhere\"",
+        );
+        let (_, errors) = changed_packages(Patch::from_multiple(&moved).unwrap());
+        let [e @ Error::OrgNameMismatch { .. }] = errors.as_slice() else {
+            panic!("expected a mismatch, got {errors:?}");
+        };
+        assert_eq!(e.location(), Some((path, 2)));
+    }
+
+    #[test]
+    fn test_all_errors() {
+        // A malformed line, a deletion and a well-formed package, in separate files.
+        let bad_line = SAMPLE_DIFF.replace(r#""v":0"#, r#""v":}"#);
+        let deletion: String = SAMPLE_YANK_DIFF
+            .replace("@@ -1 +1 @@", "@@ -1 +0,0 @@")
+            .lines()
+            .filter(|l| !l.starts_with("+{"))
+            .map(|l| format!("{l}\n"))
+            .collect();
+        let bad_path = "diff --git a/github b/github\n\
+                        --- a/github\n\
+                        +++ b/github\n\
+                        @@ -1 +1,2 @@\n \
+                        foo\n\
+                        +bar\n";
+        let diff = [&bad_line, &deletion, bad_path, SAMPLE_DIFF_WITH_SUBDIR].concat();
+        let patches = Patch::from_multiple(&diff).unwrap();
+        let (changes, errors) = changed_packages(patches);
+
+        assert!(matches!(changes.as_slice(), [Change::Added(_)]));
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                Error::Deserialize { .. },
+                Error::Deletion { .. },
+                Error::MissingOrg(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_format_check() {
+        let packages = added_packages(SAMPLE_DIFF);
+        assert!(packages[0].format_check.is_good());
+
+        let canonical = canonical_line(&packages[0].pkg);
+        let variants = [
+            // Extra whitespace.
+            canonical.replace(r#""v":0"#, r#""v": 0"#),
+            // Reordered keys.
+            canonical
+                .replace(r#","v":0}"#, "}")
+                .replacen('{', r#"{"v":0,"#, 1),
+            // An unknown field.
+            canonical.replace(r#""v":0"#, r#""v":0,"extra":1"#),
+            // A different format version.
+            canonical.replace(r#""v":0"#, r#""v":1"#),
+        ];
+        for line in variants {
+            let diff = SAMPLE_DIFF.replace(&canonical, &line);
+            assert_ne!(diff, SAMPLE_DIFF);
+            let packages = added_packages(&diff);
+            let FormatCheck::NotCanonical(expected) = &packages[0].format_check else {
+                panic!("expected {line} not to be canonical");
+            };
+            assert_eq!(expected, &canonical);
+        }
     }
 
     #[test]
     fn test_changed_packages_with_subdir() {
-        let patches = Patch::from_multiple(SAMPLE_DIFF_WITH_SUBDIR).unwrap();
-        let packages = changed_packages(patches).unwrap();
+        let packages = added_packages(SAMPLE_DIFF_WITH_SUBDIR);
         assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].line, 1);
         assert_eq!(
-            packages[0].id,
+            packages[0].pkg.id,
             PreciseId::Github {
                 org: "nickel-lang".to_owned(),
                 name: "json-schema-to-nickel".to_owned(),
@@ -0,0 +1,233 @@
+//! Checking that a package's commit was actually released.
+//!
+//! A package pins a commit, but anyone can push a commit to a branch (or open
+//! a PR from a fork, which makes github serve the fork's commits from the
+//! upstream repository). We'd like the commit to be something that the
+//! package's maintainers released: either a tag named after the version points
+//! at it, or it's on the repository's default branch.
+
+use gix::{
+    ObjectId,
+    interrupt::IS_INTERRUPTED,
+    progress::Discard,
+    remote::{Direction, fetch},
+};
+use miette::IntoDiagnostic as _;
+use nickel_lang_package::{
+    config::Config,
+    index::{Package, PreciseId},
+    version::SemVer,
+};
+use serde::Serialize;
+
+/// Where we put the remote's default branch when fetching it.
+const DEFAULT_BRANCH_REF: &str = "refs/remotes/origin/HEAD";
+
+/// The tags that we accept as releasing `version`.
+fn tag_names(version: &SemVer) -> [String; 2] {
+    [format!("v{version}"), version.to_string()]
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", content = "details", rename_all = "snake_case")]
+pub enum ProvenanceCheck {
+    /// A tag named after the version points at the commit. Contains the tag.
+    Tagged(String),
+    OnDefaultBranch,
+    /// The commit isn't tagged, and it isn't on the default branch.
+    Unreleased,
+    /// We require a tag, and none of the tags named after the version point
+    /// at the commit.
+    Untagged,
+    /// We couldn't look at the repository's refs.
+    Failed {
+        error: String,
+        /// Whether we required a tag, which we then couldn't find.
+        tag_required: bool,
+    },
+}
+
+impl ProvenanceCheck {
+    /// Checks where a package's commit comes from, fetching from wherever
+    /// `config` says that github packages come from.
+    ///
+    /// If `require_tag` is set, being on the default branch isn't enough.
+    pub fn new(pkg: &Package, config: &Config, require_tag: bool) -> Self {
+        let PreciseId::Github { commit, .. } = &pkg.id;
+        let url = pkg.id.download_spec(config).url.to_bstring().to_string();
+        check(&url, *commit, &pkg.version, require_tag).unwrap_or_else(|e| {
+            ProvenanceCheck::Failed {
+                error: e.to_string(),
+                tag_required: require_tag,
+            }
+        })
+    }
+
+    /// Only a missing tag is an error: the other problems only get a warning,
+    /// because there are legitimate reasons to release from a branch. If we
+    /// require a tag but can't look for one, that counts as missing.
+    pub fn is_good(&self) -> bool {
+        !matches!(
+            self,
+            ProvenanceCheck::Untagged
+                | ProvenanceCheck::Failed {
+                    tag_required: true,
+                    ..
+                }
+        )
+    }
+
+    pub fn format(
+        &self,
+        f: &mut std::fmt::Formatter,
+        indent: &str,
+        version: &SemVer,
+    ) -> std::fmt::Result {
+        let [v_tag, tag] = tag_names(version);
+        match self {
+            ProvenanceCheck::Tagged(tag) => writeln!(f, "{indent}✅ commit is tagged {tag}"),
+            ProvenanceCheck::OnDefaultBranch => {
+                writeln!(f, "{indent}✅ commit is on the default branch")
+            }
+            ProvenanceCheck::Unreleased => writeln!(
+                f,
+                "{indent}⚠️ commit isn't on the default branch, and isn't tagged {v_tag} or {tag}"
+            ),
+            ProvenanceCheck::Untagged => {
+                writeln!(f, "{indent}❌ commit isn't tagged {v_tag} or {tag}")
+            }
+            ProvenanceCheck::Failed {
+                error,
+                tag_required,
+            } => {
+                let sym = if *tag_required { "❌" } else { "⚠️" };
+                writeln!(
+                    f,
+                    "{indent}{sym} couldn't check where the commit comes from: {error}"
+                )
+            }
+        }
+    }
+}
+
+fn check(
+    url: &str,
+    commit: ObjectId,
+    version: &SemVer,
+    require_tag: bool,
+) -> miette::Result<ProvenanceCheck> {
+    let tags = tag_names(version);
+    let git_dir = tempfile::tempdir().into_diagnostic()?;
+    let repo = gix::init_bare(git_dir.path()).into_diagnostic()?;
+
+    let mut refspecs = vec![format!("+HEAD:{DEFAULT_BRANCH_REF}")];
+    refspecs.extend(tags.iter().map(|t| format!("refs/tags/{t}")));
+    let remote = repo
+        .remote_at(url)
+        .into_diagnostic()?
+        .with_fetch_tags(fetch::Tags::None)
+        .with_refspecs(refspecs.iter().map(String::as_str), Direction::Fetch)
+        .into_diagnostic()?;
+    let prepare = remote
+        .connect(Direction::Fetch)
+        .into_diagnostic()?
+        .prepare_fetch(&mut Discard, Default::default())
+        .into_diagnostic()?;
+
+    // The tags can be checked from the advertised refs, without fetching anything.
+    for r in &prepare.ref_map().remote_refs {
+        let (name, target, peeled) = r.unpack();
+        let Some(tag) = tags.iter().find(|t| *name == format!("refs/tags/{t}")) else {
+            continue;
+        };
+        if peeled.or(target) == Some(commit.as_ref()) {
+            return Ok(ProvenanceCheck::Tagged(tag.clone()));
+        }
+    }
+    if require_tag {
+        return Ok(ProvenanceCheck::Untagged);
+    }
+
+    prepare
+        .receive(&mut Discard, &IS_INTERRUPTED)
+        .into_diagnostic()?;
+    let head = repo
+        .find_reference(DEFAULT_BRANCH_REF)
+        .into_diagnostic()?
+        .peel_to_id_in_place()
+        .into_diagnostic()?;
+    for info in head.ancestors().all().into_diagnostic()? {
+        if info.into_diagnostic()?.id == commit {
+            return Ok(ProvenanceCheck::OnDefaultBranch);
+        }
+    }
+    Ok(ProvenanceCheck::Unreleased)
+}
+
+#[cfg(test)]
+mod tests {
+    use gix::refs::transaction::PreviousValue;
+
+    use super::*;
+    use crate::test_repo;
+
+    #[test]
+    fn test_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = test_repo::init(dir.path());
+        let first = test_repo::commit(&repo, "a@example.com", "main.ncl", "1");
+        let second = test_repo::commit(&repo, "a@example.com", "main.ncl", "2");
+        repo.tag_reference("v1.0.0", first, PreviousValue::Any)
+            .unwrap();
+        let tree = repo.find_commit(second).unwrap().tree_id().unwrap();
+        let feature = repo
+            .commit("refs/heads/feature", "unmerged", tree, [second])
+            .unwrap()
+            .detach();
+        // An annotated tag on a commit that isn't on the default branch.
+        let sig = repo.committer().unwrap().unwrap();
+        repo.tag(
+            "1.1.0",
+            feature,
+            gix::objs::Kind::Commit,
+            Some(sig),
+            "release",
+            PreviousValue::Any,
+        )
+        .unwrap();
+
+        let url = format!("file://{}", dir.path().display());
+        let check = |commit, version: &str, require_tag| {
+            check(&url, commit, &version.parse().unwrap(), require_tag).unwrap()
+        };
+
+        assert!(matches!(
+            check(first, "1.0.0", true),
+            ProvenanceCheck::Tagged(t) if t == "v1.0.0"
+        ));
+        assert!(matches!(
+            check(feature, "1.1.0", false),
+            ProvenanceCheck::Tagged(t) if t == "1.1.0"
+        ));
+        assert!(matches!(
+            check(first, "2.0.0", false),
+            ProvenanceCheck::OnDefaultBranch
+        ));
+        assert!(matches!(
+            check(feature, "2.0.0", false),
+            ProvenanceCheck::Unreleased
+        ));
+        assert!(matches!(
+            check(second, "2.0.0", true),
+            ProvenanceCheck::Untagged
+        ));
+
+        // Not being able to look is only a problem if we need a tag.
+        let failed = |tag_required| ProvenanceCheck::Failed {
+            error: "unreachable".to_owned(),
+            tag_required,
+        };
+        assert!(failed(false).is_good());
+        assert!(!failed(true).is_good());
+    }
+}
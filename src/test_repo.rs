@@ -0,0 +1,36 @@
+//! Building git repositories for tests.
+
+use gix::{ObjectId, actor::SignatureRef, object::tree::EntryKind};
+
+/// Makes a new repository in `dir`.
+pub fn init(dir: &std::path::Path) -> gix::Repository {
+    gix::init(dir).unwrap();
+    // Updating the reflog needs a committer in the config.
+    let config = dir.join(".git/config");
+    let mut contents = std::fs::read_to_string(&config).unwrap();
+    contents.push_str("[user]\n\tname = test\n\temail = test@example.com\n");
+    std::fs::write(&config, contents).unwrap();
+    gix::open(dir).unwrap()
+}
+
+/// Makes a commit on `HEAD` that sets `path` to `contents`.
+pub fn commit(repo: &gix::Repository, email: &str, path: &str, contents: &str) -> ObjectId {
+    let parent = repo.head_id().ok().map(|id| id.detach());
+    let tree = match parent {
+        Some(p) => repo.find_commit(p).unwrap().tree_id().unwrap().detach(),
+        None => ObjectId::empty_tree(repo.object_hash()),
+    };
+    let blob = repo.write_blob(contents).unwrap();
+    let mut editor = repo.edit_tree(tree).unwrap();
+    editor.upsert(path, EntryKind::Blob, blob).unwrap();
+    let tree = editor.write().unwrap();
+
+    let sig = SignatureRef {
+        name: "someone".into(),
+        email: email.into(),
+        time: Default::default(),
+    };
+    repo.commit_as(sig, sig, "HEAD", "commit", tree, parent)
+        .unwrap()
+        .detach()
+}
@@ -0,0 +1,361 @@
+//! Reading a PR's changes straight from the index repository.
+//!
+//! Instead of parsing the unified diff that github gives us, we can compare
+//! the trees of the PR's base and head commits in a local clone of the index,
+//! and diff the index files line by line ourselves. This produces the same
+//! [`FileChange`]s as [`diff::file_changes`](crate::diff::file_changes), so the
+//! rest of the checks don't care where the changes came from.
+
+use std::{collections::BTreeMap, ops::Range, path::Path};
+
+use gitpatch::{File, Hunk, Line, Patch};
+use gix::{
+    ObjectId,
+    bstr::ByteSlice as _,
+    diff::blob::{Algorithm, diff, intern::InternedInput, sources},
+    objs::tree::EntryMode,
+    traverse::tree::Recorder,
+};
+use miette::IntoDiagnostic as _;
+
+use crate::diff::FileChange;
+
+/// The changes between two commits of the index repository.
+pub struct TreeDiff {
+    changes: Vec<TreeChange>,
+}
+
+enum TreeChange {
+    /// A file that was added (if `old` is `None`), or whose contents changed.
+    Contents {
+        path: String,
+        old: Option<String>,
+        new: String,
+    },
+    /// Any other change, which doesn't need the contents of the file.
+    Other(FileChange<'static>),
+}
+
+impl TreeDiff {
+    /// Compares the tree of `head` with the tree of its merge base with
+    /// `base`, which is what github shows as the PR's changes.
+    pub fn new(repo_path: &Path, base: &str, head: &str) -> miette::Result<Self> {
+        let repo = gix::open(repo_path).into_diagnostic()?;
+        let commit = |rev: &str| -> miette::Result<ObjectId> {
+            Ok(repo
+                .rev_parse_single(rev)
+                .into_diagnostic()?
+                .object()
+                .into_diagnostic()?
+                .peel_to_commit()
+                .into_diagnostic()?
+                .id)
+        };
+        let head = commit(head)?;
+        let base = repo
+            .merge_base(commit(base)?, head)
+            .into_diagnostic()?
+            .detach();
+        // Index files are text, so anything that isn't UTF-8 gets rejected
+        // rather than checked with replacement characters in it.
+        let read = |id: ObjectId| -> miette::Result<Option<String>> {
+            let blob = repo.find_object(id).into_diagnostic()?;
+            Ok(String::from_utf8(blob.detach().data).ok())
+        };
+        let contents = |path: &String, old: Option<ObjectId>, new: ObjectId| {
+            let old = old.map(read).transpose()?;
+            Ok::<_, miette::Report>(match (old, read(new)?) {
+                (Some(None), _) | (_, None) => {
+                    TreeChange::Other(FileChange::Binary { path: path.clone() })
+                }
+                (old, Some(new)) => TreeChange::Contents {
+                    path: path.clone(),
+                    old: old.flatten(),
+                    new,
+                },
+            })
+        };
+
+        let old = files(&repo, base)?;
+        let new = files(&repo, head)?;
+        let mut changes = Vec::new();
+        let mut added = Vec::new();
+        for (path, &(mode, id)) in &new {
+            let kind = if mode.is_link() {
+                Some("symlink")
+            } else if mode.is_commit() {
+                Some("submodule")
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                // These don't have lines for us to check, and they'd point the
+                // package manager somewhere outside the index.
+                if old.get(path) != Some(&(mode, id)) {
+                    changes.push(TreeChange::Other(FileChange::NotAFile {
+                        path: path.clone(),
+                        kind: kind.to_owned(),
+                    }));
+                }
+                continue;
+            }
+            match old.get(path) {
+                None => added.push((path, id)),
+                Some(&(old_mode, _)) if old_mode != mode => {
+                    changes.push(TreeChange::Other(FileChange::ModeChanged {
+                        path: path.clone(),
+                        old_mode: old_mode.kind().as_octal_str().to_string(),
+                        new_mode: mode.kind().as_octal_str().to_string(),
+                    }));
+                }
+                Some(&(_, old_id)) if old_id != id => {
+                    changes.push(contents(path, Some(old_id), id)?)
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut deleted: Vec<_> = old
+            .iter()
+            .filter(|(path, _)| !new.contains_key(*path))
+            .map(|(path, &(_, id))| (path, id))
+            .collect();
+        for (path, id) in added {
+            // Like git, we say that a file was renamed if its exact contents
+            // disappeared from somewhere else.
+            if let Some(i) = deleted.iter().position(|&(_, old_id)| old_id == id) {
+                let (from, _) = deleted.remove(i);
+                changes.push(TreeChange::Other(FileChange::Renamed {
+                    from: from.clone(),
+                    to: path.clone(),
+                }));
+            } else {
+                changes.push(contents(path, None, id)?);
+            }
+        }
+        changes.extend(
+            deleted
+                .into_iter()
+                .map(|(path, _)| TreeChange::Other(FileChange::Deleted { path: path.clone() })),
+        );
+
+        Ok(Self { changes })
+    }
+
+    pub fn file_changes(&self) -> Vec<FileChange<'_>> {
+        self.changes
+            .iter()
+            .map(|change| match change {
+                TreeChange::Contents { path, old, new } => {
+                    let patch = patch(path, old.as_deref(), new);
+                    if old.is_some() {
+                        FileChange::Modified(patch)
+                    } else {
+                        FileChange::Added(patch)
+                    }
+                }
+                TreeChange::Other(change) => change.clone(),
+            })
+            .collect()
+    }
+}
+
+/// All the files (and symlinks and submodules) in a commit, by path.
+fn files(
+    repo: &gix::Repository,
+    commit: ObjectId,
+) -> miette::Result<BTreeMap<String, (EntryMode, ObjectId)>> {
+    let tree = repo
+        .find_commit(commit)
+        .into_diagnostic()?
+        .tree()
+        .into_diagnostic()?;
+    let mut recorder = Recorder::default();
+    tree.traverse()
+        .breadthfirst(&mut recorder)
+        .into_diagnostic()?;
+    Ok(recorder
+        .records
+        .into_iter()
+        .filter(|e| !e.mode.is_tree())
+        .map(|e| (e.filepath.to_str_lossy().into_owned(), (e.mode, e.oid)))
+        .collect())
+}
+
+/// Builds a patch that turns `old` into `new`, with one hunk (and no context
+/// lines) for each run of changed lines.
+///
+/// The paths are written the way `git diff` writes them, with "a/" and "b/"
+/// prefixes and `/dev/null` for a new file. Like `git diff`, only the final
+/// "\n" of each line is stripped, so a "\r" before it stays visible to the
+/// checks, and a missing newline at the end of the file is recorded in
+/// `end_newline`.
+fn patch<'a>(path: &str, old: Option<&'a str>, new: &'a str) -> Patch<'a> {
+    let input = InternedInput::new(
+        sources::lines_with_terminator(old.unwrap_or("")),
+        sources::lines_with_terminator(new),
+    );
+    let line = |token| {
+        let line: &str = input.interner[token];
+        line.strip_suffix('\n').unwrap_or(line)
+    };
+    let mut hunks = Vec::new();
+    diff(
+        Algorithm::Histogram,
+        &input,
+        |before: Range<u32>, after: Range<u32>| {
+            let removed = before
+                .clone()
+                .map(|i| Line::Remove(line(input.before[i as usize])));
+            let added = after
+                .clone()
+                .map(|i| Line::Add(line(input.after[i as usize])));
+            hunks.push(Hunk {
+                old_range: hunk_range(&before),
+                new_range: hunk_range(&after),
+                range_hint: "",
+                lines: removed.chain(added).collect(),
+            });
+        },
+    );
+
+    Patch {
+        old: File {
+            path: old.map_or("/dev/null".into(), |_| format!("a/{path}").into()),
+            meta: None,
+        },
+        new: File {
+            path: format!("b/{path}").into(),
+            meta: None,
+        },
+        hunks,
+        end_newline: new.is_empty() || new.ends_with('\n'),
+    }
+}
+
+/// Converts a 0-based range of lines to a 1-based hunk range.
+fn hunk_range(lines: &Range<u32>) -> gitpatch::Range {
+    gitpatch::Range {
+        start: u64::from(lines.start) + 1,
+        count: u64::from(lines.end - lines.start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gix::object::tree::EntryKind;
+
+    use super::*;
+    use crate::test_repo::{commit, init};
+
+    #[test]
+    fn test_tree_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init(dir.path());
+        commit(&repo, "a@example.com", "github/org/pkg", "one\ntwo\n");
+        commit(&repo, "a@example.com", "github/org/old", "old\n");
+        commit(&repo, "a@example.com", "github/org/exe", "exe\n");
+        let base = commit(&repo, "a@example.com", "github/org/a", "a\n");
+
+        // A PR that does a bit of everything.
+        let tree = repo.find_commit(base).unwrap().tree_id().unwrap();
+        let mut editor = repo.edit_tree(tree).unwrap();
+        let blob = |contents: &str| repo.write_blob(contents).unwrap().detach();
+        editor
+            .upsert("github/org/pkg", EntryKind::Blob, blob("one\nTWO\nthree\n"))
+            .unwrap()
+            .upsert("github/org/new", EntryKind::Blob, blob("new\n"))
+            .unwrap()
+            .upsert("github/org/exe", EntryKind::BlobExecutable, blob("exe\n"))
+            .unwrap()
+            .upsert("github/org/b", EntryKind::Blob, blob("a\n"))
+            .unwrap()
+            .remove("github/org/a")
+            .unwrap()
+            .remove("github/org/old")
+            .unwrap();
+        let tree = editor.write().unwrap();
+        repo.commit("refs/heads/pr", "pr", tree, [base]).unwrap();
+        // The base branch moves on after the PR was opened.
+        commit(&repo, "a@example.com", "github/org/later", "later\n");
+
+        let tree_diff = TreeDiff::new(dir.path(), "HEAD", "pr").unwrap();
+        let changes = tree_diff.file_changes();
+        assert_eq!(changes.len(), 5);
+        assert!(matches!(
+            &changes[0],
+            FileChange::ModeChanged { path, old_mode, new_mode }
+                if path == "github/org/exe" && old_mode == "100644" && new_mode == "100755"
+        ));
+        let FileChange::Modified(patch) = &changes[1] else {
+            panic!("expected a modification, got {:?}", changes[1]);
+        };
+        assert_eq!(patch.new.path, "b/github/org/pkg");
+        assert_eq!(patch.hunks.len(), 1);
+        assert_eq!(patch.hunks[0].new_range.start, 2);
+        assert_eq!(
+            patch.hunks[0].lines,
+            [Line::Remove("two"), Line::Add("TWO"), Line::Add("three")]
+        );
+        assert!(matches!(
+            &changes[2],
+            FileChange::Renamed { from, to } if from == "github/org/a" && to == "github/org/b"
+        ));
+        assert!(matches!(
+            &changes[3],
+            FileChange::Added(p) if p.old.path == "/dev/null" && p.hunks[0].lines == [Line::Add("new")]
+        ));
+        assert!(matches!(
+            &changes[4],
+            FileChange::Deleted { path } if path == "github/org/old"
+        ));
+    }
+
+    #[test]
+    fn test_rejected_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init(dir.path());
+        commit(&repo, "a@example.com", "github/org/crlf", "one\n");
+        let base = commit(&repo, "a@example.com", "github/org/short", "one\n");
+
+        let tree = repo.find_commit(base).unwrap().tree_id().unwrap();
+        let mut editor = repo.edit_tree(tree).unwrap();
+        let blob = |contents: &[u8]| repo.write_blob(contents).unwrap().detach();
+        editor
+            .upsert("github/org/crlf", EntryKind::Blob, blob(b"one\ntwo\r\n"))
+            .unwrap()
+            .upsert("github/org/short", EntryKind::Blob, blob(b"one\ntwo"))
+            .unwrap()
+            .upsert("github/org/bin", EntryKind::Blob, blob(b"\xff\xfe\n"))
+            .unwrap()
+            .upsert("github/org/link", EntryKind::Link, blob(b"../../etc"))
+            .unwrap()
+            .upsert("github/org/sub", EntryKind::Commit, base)
+            .unwrap();
+        let tree = editor.write().unwrap();
+        repo.commit("refs/heads/pr", "pr", tree, [base]).unwrap();
+
+        let tree_diff = TreeDiff::new(dir.path(), "HEAD", "pr").unwrap();
+        let changes = tree_diff.file_changes();
+        assert_eq!(changes.len(), 5);
+        let FileChange::Modified(crlf) = &changes[0] else {
+            panic!("expected a modification, got {:?}", changes[0]);
+        };
+        assert_eq!(crlf.hunks[0].lines, [Line::Add("two\r")]);
+        assert!(crlf.end_newline);
+        assert!(matches!(
+            &changes[1],
+            FileChange::NotAFile { path, kind } if path == "github/org/link" && kind == "symlink"
+        ));
+        let FileChange::Modified(short) = &changes[2] else {
+            panic!("expected a modification, got {:?}", changes[2]);
+        };
+        assert_eq!(short.hunks[0].lines, [Line::Add("two")]);
+        assert!(!short.end_newline);
+        assert!(matches!(
+            &changes[3],
+            FileChange::NotAFile { path, kind } if path == "github/org/sub" && kind == "submodule"
+        ));
+        assert!(matches!(&changes[4], FileChange::Binary { path } if path == "github/org/bin"));
+    }
+}